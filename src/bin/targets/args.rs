@@ -0,0 +1,186 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    /// The arguments are wrong, so the usage is shown as well.
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// Describe what failed, such as `result.context("Could not open input file")`.
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T, CliError>;
+}
+
+impl<T, E: Display> Context<T> for Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T, CliError> {
+        self.map_err(|err| CliError::Failed(format!("{context}: {err}")))
+    }
+}
+
+/// Arguments of a command.
+///
+/// Named options are taken out first, wherever they are,
+/// and the remaining arguments are positional.
+pub struct Args {
+    args: Vec<String>,
+    /// The options which take a value, as opposed to flags.
+    options: &'static [&'static str],
+}
+
+impl Args {
+    pub fn new(args: Vec<String>, options: &'static [&'static str]) -> Self {
+        Self { args, options }
+    }
+
+    /// Whether `--help` or `-h` was given, other than as the value of an option,
+    /// such as `--columns -h`.
+    pub fn help(&self) -> bool {
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return true;
+            }
+            if self.options.contains(&arg.as_str()) {
+                args.next();
+            }
+        }
+        false
+    }
+
+    /// A switch without a value, such as `--summary`.
+    pub fn flag(&mut self, name: &str) -> bool {
+        let len = self.args.len();
+        self.args.retain(|arg| arg != name);
+        self.args.len() != len
+    }
+
+    /// An option with a value, such as `--size 6` or `--size=6`.
+    pub fn option<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, CliError>
+    where
+        T::Err: Display,
+    {
+        debug_assert!(self.options.contains(&name), "undeclared option {name}");
+        let prefix = format!("{name}=");
+        let Some(i) = self
+            .args
+            .iter()
+            .position(|arg| arg == name || arg.starts_with(&prefix))
+        else {
+            return Ok(None);
+        };
+        let arg = self.args.remove(i);
+        let value = match arg.strip_prefix(&prefix) {
+            Some(value) => value.to_string(),
+            None if i < self.args.len() => self.args.remove(i),
+            None => return Err(CliError::Usage(format!("Missing the value for {name}"))),
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|err| CliError::Usage(format!("Invalid value {value} for {name}: {err}")))
+    }
+
+    pub fn required<T: FromStr>(&mut self, name: &str) -> Result<T, CliError>
+    where
+        T::Err: Display,
+    {
+        self.option(name)?
+            .ok_or_else(|| CliError::Usage(format!("Missing {name}")))
+    }
+
+    /// The board size given with `--size`.
+    pub fn size(&mut self) -> Result<usize, CliError> {
+        self.optional_size()?
+            .ok_or_else(|| CliError::Usage("Missing --size".to_string()))
+    }
+
+    pub fn optional_size(&mut self) -> Result<Option<usize>, CliError> {
+        let size = self.option("--size")?;
+        if let Some(size) = size {
+            if !(3..=8).contains(&size) {
+                return Err(CliError::Usage(format!("Unsupported board size {size}")));
+            }
+        }
+        Ok(size)
+    }
+
+    /// The next positional argument.
+    pub fn positional(&mut self, name: &str) -> Result<String, CliError> {
+        let i = self
+            .args
+            .iter()
+            .position(|arg| !arg.starts_with("--"))
+            .ok_or_else(|| CliError::Usage(format!("Missing {name}")))?;
+        Ok(self.args.remove(i))
+    }
+
+    /// All remaining positional arguments.
+    pub fn rest(&mut self) -> Vec<String> {
+        let (rest, options) = std::mem::take(&mut self.args)
+            .into_iter()
+            .partition(|arg| !arg.starts_with("--"));
+        self.args = options;
+        rest
+    }
+
+    /// Check that every argument was used.
+    pub fn finish(self) -> Result<(), CliError> {
+        match self.args.first() {
+            Some(arg) => Err(CliError::Usage(format!("Unexpected argument {arg}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn open_input(path: &str) -> Result<BufReader<File>, CliError> {
+    let input = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .context(format_args!("Could not open input file {path}"))?;
+    Ok(BufReader::new(input))
+}
+
+pub fn create_output(path: &str) -> Result<BufWriter<File>, CliError> {
+    let output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .context(format_args!(
+            "Could not open or create the output file {path}"
+        ))?;
+    Ok(BufWriter::new(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+
+    fn args(args: &[&str]) -> Args {
+        let options = &["--output", "--size", "--columns"];
+        Args::new(args.iter().map(|arg| arg.to_string()).collect(), options)
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(args(&["--help"]).help());
+        assert!(args(&["input.txt", "-h"]).help());
+        assert!(args(&["--size=6", "-h"]).help());
+        assert!(args(&["--size", "6", "--help"]).help());
+        assert!(!args(&["input.txt", "--output", "-h"]).help());
+        assert!(!args(&["--columns", "-h", "--size", "6"]).help());
+        assert!(!args(&["input.txt"]).help());
+        assert!(args(&["in", "--output", "out", "--size", "3", "--strict", "-h"]).help());
+        assert!(args(&["--summary", "--help"]).help());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+use compress_targets::{
+    codec::Decoder,
+    json::{write_number, write_string},
+    Target,
+};
+use fast_tak::Reserves;
+use takparse::Move;
+
+use crate::args::{open_input, Args, CliError};
+
+pub const USAGE: &str = "Usage:
+    targets check <path/to/original> <path/to/converted> [--size <size_of_board>] [--summary]
+
+Prints the losses for every record, or with --summary
+a JSON report with the mean, median, 99th percentile, and maximum of each metric.
+Records which do not match are listed instead of compared.
+Only the targets which both files have are compared, so a value-only file can be checked against the original.
+Policies are matched by move, so the original may list its moves in any order and leave some out,
+which then have a probability of zero.
+
+With --size the converted file is a compressed file, which is decoded directly.
+";
+
+pub const OPTIONS: &[&str] = &["--size"];
+
+type Targets = Box<dyn Iterator<Item = Result<Target, String>>>;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let summary = args.flag("--summary");
+    let size = args.optional_size()?;
+    let original_path = args.positional("<path/to/original>")?;
+    let converted_path = args.positional("<path/to/converted>")?;
+    args.finish()?;
+
+    let original = open_input(&original_path)?;
+    let converted = open_input(&converted_path)?;
+    let mut original_targets = parsed(original, "original");
+    let mut converted_targets = match size {
+        None => parsed(converted, "converted"),
+        Some(3) => decoded::<3>(converted),
+        Some(4) => decoded::<4>(converted),
+        Some(5) => decoded::<5>(converted),
+        Some(6) => decoded::<6>(converted),
+        Some(7) => decoded::<7>(converted),
+        Some(8) => decoded::<8>(converted),
+        Some(_) => unreachable!("the size was checked"),
+    };
+
+    let mut report = Report::default();
+    for i in 0.. {
+        let (og, cv) = match (original_targets.next(), converted_targets.next()) {
+            (None, None) => break,
+            (Some(_), None) => {
+                report.mismatch(i, "the converted file ends early".to_string(), summary);
+                break;
+            }
+            (None, Some(_)) => {
+                report.mismatch(i, "the original file ends early".to_string(), summary);
+                break;
+            }
+            (Some(og), Some(cv)) => (og, cv),
+        };
+        match og.and_then(|og| cv.map(|cv| (og, cv))) {
+            Ok((og, cv)) => report.compare(i, &og, &cv, summary),
+            Err(reason) => report.mismatch(i, reason, summary),
+        }
+    }
+
+    if summary {
+        println!("{}", report.to_json());
+    }
+    if !report.mismatches.is_empty() {
+        return Err(CliError::Failed(format!(
+            "{} records did not match",
+            report.mismatches.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Targets parsed from the lines of a text file.
+fn parsed(input: BufReader<File>, name: &'static str) -> Targets {
+    Box::new(input.lines().map(move |line| {
+        line.map_err(|err| format!("could not read {name}: {err}"))
+            .and_then(|line| {
+                line.parse::<Target>()
+                    .map_err(|err| format!("could not parse {name}: {err}"))
+            })
+    }))
+}
+
+/// Targets decoded from a compressed file, in the same form as `targets decompress` writes them.
+/// Decoding stops at the first error.
+fn decoded<const N: usize>(input: BufReader<File>) -> Targets
+where
+    Reserves<N>: Default,
+{
+    let mut failed = false;
+    Box::new(Decoder::<N, _>::new(input).map_while(move |record| {
+        if failed {
+            return None;
+        }
+        Some(match record {
+            Ok(record) => Ok(record.to_target()),
+            Err(err) => {
+                failed = true;
+                Err(format!("could not decode converted: {err}"))
+            }
+        })
+    }))
+}
+
+/// Losses of all compared records, kept so that percentiles can be computed.
+#[derive(Default)]
+struct Report {
+    records: usize,
+    compared: usize,
+    value_errors: Vec<f32>,
+    ube_errors: Vec<f32>,
+    kl_divergences: Vec<f32>,
+    total_variations: Vec<f32>,
+    top1_agreements: Vec<f32>,
+    mismatches: Vec<(usize, String)>,
+    mean_value_loss: f64,
+    mean_kl_divergence: f64,
+}
+
+impl Report {
+    fn mismatch(&mut self, i: usize, reason: String, summary: bool) {
+        self.records = self.records.max(i + 1);
+        if !summary {
+            eprintln!("Mismatch at record [{i}]: {reason}");
+        }
+        self.mismatches.push((i, reason));
+    }
+
+    fn compare(&mut self, i: usize, og: &Target, cv: &Target, summary: bool) {
+        if og.tps.board().collect::<Vec<_>>() != cv.tps.board().collect::<Vec<_>>() {
+            return self.mismatch(i, "the boards differ".to_string(), summary);
+        }
+        if og.tps.color() != cv.tps.color() {
+            return self.mismatch(i, "the sides to move differ".to_string(), summary);
+        }
+        if let (Some(og_result), Some(cv_result)) = (og.result, cv.result) {
+            if og_result != cv_result {
+                let reason = format!("the results {og_result} and {cv_result} differ");
+                return self.mismatch(i, reason, summary);
+            }
+        }
+        // Visit counts are stored losslessly, but unvisited moves may be left out.
+        let same_visits = og.visits.as_ref().zip(cv.visits.as_ref()).map(|(og, cv)| {
+            let sorted = |counts: &[(Move, u32)]| {
+                let mut counts: Vec<_> = counts.iter().filter(|&&(_, c)| c > 0).copied().collect();
+                counts.sort();
+                counts
+            };
+            og.total == cv.total && sorted(&og.counts) == sorted(&cv.counts)
+        });
+        if same_visits == Some(false) {
+            return self.mismatch(i, "the visit counts differ".to_string(), summary);
+        }
+        let distances = match (&og.policy, &cv.policy) {
+            (Some(_), Some(_)) if same_visits == Some(true) => Some((0.0, 0.0)),
+            (Some(og_policy), Some(cv_policy)) => match policy_distances(og_policy, cv_policy) {
+                Ok(distances) => Some(distances),
+                Err(reason) => return self.mismatch(i, reason, summary),
+            },
+            // Only the channels which both files store are compared.
+            _ => None,
+        };
+        self.records = self.records.max(i + 1);
+        self.compared += 1;
+
+        let value_error = og
+            .value
+            .zip(cv.value)
+            .map(|(og_value, cv_value)| (og_value as f64 - cv_value as f64).abs());
+        if let Some(value_error) = value_error {
+            self.value_errors.push(value_error as f32);
+        }
+        if let (Some(og_ube), Some(cv_ube)) = (og.ube, cv.ube) {
+            if !og_ube.is_nan() && !cv_ube.is_nan() {
+                self.ube_errors.push((og_ube - cv_ube).abs());
+            }
+        }
+        if let (Some((kl_divergence, total_variation)), Some(og_policy), Some(cv_policy)) =
+            (distances, &og.policy, &cv.policy)
+        {
+            self.kl_divergences.push(kl_divergence as f32);
+            self.total_variations.push(total_variation as f32);
+            let top1_agreement = top1(og_policy) == top1(cv_policy);
+            self.top1_agreements
+                .push(if top1_agreement { 1.0 } else { 0.0 });
+        }
+
+        if !summary {
+            let value_loss = value_error.map_or(0.0, |error| error * error);
+            let kl_divergence = distances.map_or(0.0, |(kl_divergence, _)| kl_divergence);
+            let n = self.compared as f64;
+            update_mean(&mut self.mean_value_loss, value_loss, n - 1.0);
+            update_mean(&mut self.mean_kl_divergence, kl_divergence, n - 1.0);
+            println!(
+                "vl: {value_loss}, \tmean vl: {}, \tkl: {kl_divergence}, \tmean_kl: {}",
+                self.mean_value_loss, self.mean_kl_divergence
+            );
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut s = String::new();
+        let compared = self.compared;
+        write!(
+            s,
+            r#"{{"records":{},"compared":{compared},"mismatched":{}"#,
+            self.records,
+            self.mismatches.len()
+        )
+        .unwrap();
+        for (name, metric) in [
+            ("value_error", &self.value_errors),
+            ("ube_error", &self.ube_errors),
+            ("kl_divergence", &self.kl_divergences),
+            ("total_variation", &self.total_variations),
+            ("top1_agreement", &self.top1_agreements),
+        ] {
+            write!(s, r#","{name}":"#).unwrap();
+            write_statistics(&mut s, metric).unwrap();
+        }
+        s.push_str(r#","mismatches":["#);
+        for (i, (record, reason)) in self.mismatches.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(s, r#"{{"record":{record},"reason":"#).unwrap();
+            write_string(&mut s, reason, &[]).unwrap();
+            s.push('}');
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+fn update_mean(mean: &mut f64, new: f64, i: f64) {
+    *mean += (new - *mean) / (i + 1.0);
+}
+
+/// Write the mean, median, 99th percentile, and maximum as a JSON object,
+/// or `null` if there is no data.
+fn write_statistics(s: &mut String, xs: &[f32]) -> std::fmt::Result {
+    if xs.is_empty() {
+        s.push_str("null");
+        return Ok(());
+    }
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let percentile = |q: f64| sorted[((sorted.len() - 1) as f64 * q).round() as usize];
+    let mean = xs.iter().map(|&x| f64::from(x)).sum::<f64>() / xs.len() as f64;
+    for (i, (name, x)) in [
+        ("mean", mean as f32),
+        ("p50", percentile(0.5)),
+        ("p99", percentile(0.99)),
+        ("max", sorted[sorted.len() - 1]),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        s.push(if i == 0 { '{' } else { ',' });
+        write!(s, r#""{name}":"#)?;
+        write_number(s, x)?;
+    }
+    s.push('}');
+    Ok(())
+}
+
+/// The KL divergence and the total variation distance between two policies.
+/// The policies are matched by move, and a move which only one of them lists
+/// has a probability of zero in the other.
+fn policy_distances(p: &[(Move, f32)], q: &[(Move, f32)]) -> Result<(f64, f64), String> {
+    let p = by_move(p)?;
+    let q = by_move(q)?;
+    let moves: BTreeSet<Move> = p.keys().chain(q.keys()).copied().collect();
+    let mut kl_divergence = 0.0;
+    let mut total_variation = 0.0;
+    for action in moves {
+        let probability = |policy: &BTreeMap<Move, f32>| {
+            f64::from(policy.get(&action).copied().unwrap_or(0.0)).max(1e-16)
+        };
+        let p_x = probability(&p);
+        let q_x = probability(&q);
+        kl_divergence += p_x * (p_x / q_x).ln();
+        total_variation += (p_x - q_x).abs() / 2.0;
+    }
+    Ok((kl_divergence, total_variation))
+}
+
+fn by_move(policy: &[(Move, f32)]) -> Result<BTreeMap<Move, f32>, String> {
+    let mut by_move = BTreeMap::new();
+    for &(action, probability) in policy {
+        if by_move.insert(action, probability).is_some() {
+            return Err(format!("the policy repeats the move {action}"));
+        }
+    }
+    Ok(by_move)
+}
+
+/// The move with the highest probability.
+fn top1(policy: &[(Move, f32)]) -> Option<Move> {
+    policy
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|&(action, _)| action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_order() {
+        // The original lists the top moves out of order, as `targets compress` accepts.
+        let og: Target = "x3/x3/x3 1 1;0.5;b2:0.25,a1:0.75".parse().unwrap();
+        let mut cv = og.clone();
+        let mut actions = vec![];
+        fast_tak::Game::<3, 0>::from(cv.tps.clone()).possible_moves(&mut actions);
+        cv.align_policy(&actions).unwrap();
+        assert_eq!(cv.policy.as_ref().unwrap().len(), 9);
+
+        let mut report = Report::default();
+        report.compare(0, &og, &cv, true);
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.compared, 1);
+        assert!(report.kl_divergences[0] < 1e-6);
+        assert!(report.total_variations[0] < 1e-6);
+
+        let repeated: Target = "x3/x3/x3 1 1;0.5;a1:0.5,a1:0.5".parse().unwrap();
+        report.compare(1, &repeated, &cv, true);
+        assert_eq!(report.mismatches.len(), 1);
+    }
+
+    #[test]
+    fn test_report_json() {
+        let mut report = Report::default();
+        report.mismatch(3, "bad \"tps\"\n\u{7}".to_string(), true);
+        report.value_errors = vec![0.5, f32::NAN];
+        let json = report.to_json();
+        assert!(
+            json.contains(r#"{"record":3,"reason":"bad \"tps\"\n\u0007"}"#),
+            "{json}"
+        );
+        assert!(
+            json.contains(r#""value_error":{"mean":null,"p50":null,"p99":null,"max":null}"#),
+            "{json}"
+        );
+        assert!(json.contains(r#""ube_error":null"#), "{json}");
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut report = Report {
+            records: 101,
+            compared: 101,
+            // Out of order, since the percentiles sort them.
+            value_errors: (0..=100).rev().map(|x| x as f32).collect(),
+            kl_divergences: vec![0.25],
+            total_variations: vec![0.0, 0.5],
+            top1_agreements: vec![1.0, 0.0, 1.0, 1.0],
+            ..Report::default()
+        };
+        report.mismatch(101, "the original file ends early".to_string(), true);
+        assert_eq!(
+            report.to_json(),
+            concat!(
+                r#"{"records":102,"compared":101,"mismatched":1,"#,
+                r#""value_error":{"mean":50,"p50":50,"p99":99,"max":100},"#,
+                r#""ube_error":null,"#,
+                r#""kl_divergence":{"mean":0.25,"p50":0.25,"p99":0.25,"max":0.25},"#,
+                r#""total_variation":{"mean":0.25,"p50":0.5,"p99":0.5,"max":0.5},"#,
+                r#""top1_agreement":{"mean":0.75,"p50":1,"p99":1,"max":1},"#,
+                r#""mismatches":[{"record":101,"reason":"the original file ends early"}]}"#,
+            )
+        );
+    }
+}
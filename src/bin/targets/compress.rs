@@ -0,0 +1,407 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+};
+
+use compress_targets::{
+    codec::{Encoder, Record},
+    header::Header,
+    ptn::{annotated_positions, split_games, PtnError},
+    AlignError, Alignment, ParseTargetError, Target,
+};
+use fast_tak::{Game, Reserves};
+use takparse::{Move, Ptn};
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets compress <path/to/input> --output <path/to/output> --size <size_of_board> [--format text|jsonl|ptn]
+        [--strict] [--reject-file <path/to/rejects>] [--repair]
+        [--channels value,ube,policy|visits,result] [--columns <name>:int|float|str,...]
+
+Formats:
+    text   one `tps;value;ube;policy` line per target, where the UBE is optional (default)
+    jsonl  one JSON object per target, as written by `targets decompress --format jsonl`
+    ptn    PTN games with `{value;policy}` or `{value;ube;policy}` comments,
+           where the comment on a move is the target for the position after it
+
+The policy is matched to the legal moves by move, so it may be in any order and leave out moves,
+which then get a probability of zero. Inputs which cannot be parsed, whose value is not in [-1, 1],
+whose UBE is not finite, or whose policy has an illegal or repeated move, or a NaN, negative, or above one probability,
+are skipped and counted by the kind of error.
+With --repair the illegal moves and bad probabilities are dropped, probabilities above one are clamped,
+and the policy is renormalized instead. With --strict the first such input aborts instead.
+The reject file gets a `# <location>: <reason>` line followed by each skipped input.
+
+Channels:
+    The targets which are stored for every record, `value,policy` by default.
+    Other channels are declared in a header at the start of the output,
+    such as `--channels value` for value-only data. Inputs without a declared channel are skipped.
+    The `visits` channel stores the policy losslessly as the visit counts of the search,
+    which are read from a policy field that starts with the total, such as `tps;value;800,a1:500,b2:299`,
+    or the `visits` object of JSON input.
+    The `result` channel stores the final result of each game once per run of relative records.
+    It is read from the sixth field of text input, such as `tps;value;;policy;;R-0`,
+    the `result` field of JSON input, or the result of PTN games, and may be left out.
+
+Columns:
+    Metadata columns such as `--columns game:int,ply:int,network:str,outcome:float`.
+    The cells are read from the `metadata` object of JSON input or the fifth field of text input,
+    such as `tps;value;;policy;game=12,ply=3,network=\"gen-42\"`, and may be left out.
+";
+
+pub const OPTIONS: &[&str] = &[
+    "--output",
+    "--size",
+    "--format",
+    "--reject-file",
+    "--channels",
+    "--columns",
+];
+
+#[derive(Clone, Copy)]
+enum Format {
+    Text,
+    JsonLines,
+    Ptn,
+}
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let format = match args.option::<String>("--format")?.as_deref() {
+        None | Some("text") => Format::Text,
+        Some("jsonl") => Format::JsonLines,
+        Some("ptn") => Format::Ptn,
+        Some(name) => return Err(CliError::Usage(format!("Unknown format {name}"))),
+    };
+    let strict = args.flag("--strict");
+    let repair = args.flag("--repair");
+    let reject_path: Option<String> = args.option("--reject-file")?;
+    let channels: Option<String> = args.option("--channels")?;
+    let columns: Option<String> = args.option("--columns")?;
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let mut header = Header {
+        columns: columns
+            .iter()
+            .flat_map(|columns| columns.split(','))
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(CliError::Usage)?,
+        ..Default::default()
+    };
+    if let Some(channels) = channels {
+        header.value = false;
+        header.policy = false;
+        for channel in channels.split(',') {
+            match channel {
+                "value" => header.value = true,
+                "ube" => header.ube = true,
+                "policy" => header.policy = true,
+                "visits" => {
+                    header.policy = true;
+                    header.visits = true;
+                }
+                "result" => header.result = true,
+                _ => return Err(CliError::Usage(format!("Unknown channel {channel}"))),
+            }
+        }
+    }
+    if !header.value && !header.ube && !header.policy && header.columns.is_empty() {
+        return Err(CliError::Usage("No channels to store".to_string()));
+    }
+
+    let input = open_input(&input_path)?;
+    let mut output = create_output(&output_path)?;
+    header
+        .write(&mut output)
+        .context("Could not write to the output file")?;
+    let mut rejects = Rejects {
+        strict,
+        output: reject_path.as_deref().map(create_output).transpose()?,
+        counts: BTreeMap::new(),
+    };
+    match size {
+        3 => compress::<3>(input, &mut output, format, header, repair, &mut rejects),
+        4 => compress::<4>(input, &mut output, format, header, repair, &mut rejects),
+        5 => compress::<5>(input, &mut output, format, header, repair, &mut rejects),
+        6 => compress::<6>(input, &mut output, format, header, repair, &mut rejects),
+        7 => compress::<7>(input, &mut output, format, header, repair, &mut rejects),
+        8 => compress::<8>(input, &mut output, format, header, repair, &mut rejects),
+        _ => unreachable!("the size was checked"),
+    }?;
+    output
+        .flush()
+        .context("Could not write to the output file")?;
+    rejects.finish()?;
+    println!("Successfully compressed targets.");
+    Ok(())
+}
+
+fn compress<const N: usize>(
+    mut input: impl BufRead,
+    output: &mut impl Write,
+    format: Format,
+    header: Header,
+    repair: bool,
+    rejects: &mut Rejects,
+) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut compressor = Compressor::<N> {
+        encoder: Encoder::with_header(header),
+        repair,
+        ..Default::default()
+    };
+    if let Format::Ptn = format {
+        let mut games = String::new();
+        input
+            .read_to_string(&mut games)
+            .context("Could not read input file")?;
+        let mut unannotated = 0;
+        for (i, game) in split_games(&games).enumerate() {
+            let positions = game
+                .parse::<Ptn>()
+                .map_err(PtnError::from)
+                .and_then(|ptn| annotated_positions::<N>(&ptn));
+            let positions = match positions {
+                Ok(positions) => positions,
+                Err(err) => {
+                    rejects.reject(format_args!("game {}", i + 1), err.kind(), err, game)?;
+                    continue;
+                }
+            };
+            for (ply, (state, target)) in positions.into_iter().enumerate() {
+                let Some(target) = target else {
+                    unannotated += 1;
+                    continue;
+                };
+                let mut target = target;
+                let line = target.to_string();
+                if let Err(err) = compressor.validate(&mut target) {
+                    let location = format_args!("game {}, ply {ply}", i + 1);
+                    rejects.reject(location, err.kind(), err, &line)?;
+                    continue;
+                }
+                if let Err(err) = compressor.align(&state, &mut target) {
+                    let location = format_args!("game {}, ply {ply}", i + 1);
+                    rejects.reject(location, err.kind(), err, &line)?;
+                    continue;
+                }
+                compressor.write(output, &state, &target, line.len())?;
+            }
+        }
+        if unannotated > 0 {
+            println!("Skipped {unannotated} positions without a target comment.");
+        }
+        compressor.report();
+        return Ok(());
+    }
+
+    for (i, maybe_line) in input.lines().enumerate() {
+        let location = format_args!("line {}", i + 1);
+        let line = match maybe_line {
+            Ok(line) => line,
+            Err(err) => {
+                rejects.reject(location, "Read", err, "")?;
+                continue;
+            }
+        };
+        let target = match format {
+            Format::JsonLines => Target::from_json(&line),
+            _ => line.parse(),
+        };
+        let mut target = match target {
+            Ok(target) => target,
+            Err(err) => {
+                rejects.reject(location, err.kind(), err, &line)?;
+                continue;
+            }
+        };
+        if let Err(err) = compressor.validate(&mut target) {
+            rejects.reject(location, err.kind(), err, &line)?;
+            continue;
+        }
+        let state = Game::<N, 4>::from(target.tps.clone());
+        if let Err(err) = compressor.align(&state, &mut target) {
+            rejects.reject(location, err.kind(), err, &line)?;
+            continue;
+        }
+        compressor.write(output, &state, &target, line.len())?;
+    }
+    compressor.report();
+    Ok(())
+}
+
+/// Inputs which were skipped, counted by the kind of error.
+struct Rejects {
+    strict: bool,
+    output: Option<BufWriter<File>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Rejects {
+    /// Skip an input, or fail in strict mode.
+    fn reject(
+        &mut self,
+        location: impl Display,
+        kind: &'static str,
+        reason: impl Display,
+        input: &str,
+    ) -> Result<(), CliError> {
+        if self.strict {
+            return Err(CliError::Failed(format!("Rejected {location}: {reason}")));
+        }
+        eprintln!("Skipping {location}: {reason}");
+        *self.counts.entry(kind).or_default() += 1;
+        if let Some(output) = &mut self.output {
+            writeln!(output, "# {location}: {reason}\n{}", input.trim_end())
+                .context("Could not write to the reject file")?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), CliError> {
+        if let Some(mut output) = self.output {
+            output
+                .flush()
+                .context("Could not write to the reject file")?;
+        }
+        let skipped: usize = self.counts.values().sum();
+        if skipped > 0 {
+            println!("Skipped {skipped} inputs:");
+            for (kind, count) in self.counts {
+                println!("  {kind:<18} {count:>8}");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Compressor<const N: usize> {
+    encoder: Encoder<N>,
+    action_buffer: Vec<Move>,
+    records: usize,
+    original_size: usize,
+    written: usize,
+    repair: bool,
+    repaired: usize,
+    reordered: usize,
+    filled: usize,
+    filled_moves: usize,
+}
+
+impl<const N: usize> Compressor<N>
+where
+    Reserves<N>: Default,
+{
+    /// Check that the target has the declared channels, and check the value, the UBE,
+    /// and the policy, or repair the policy with `--repair`.
+    /// Channels which are not stored are dropped instead.
+    /// Sums other than one are allowed unless repairing,
+    /// since a policy which only lists the top moves does not sum to one.
+    fn validate(&mut self, target: &mut Target) -> Result<(), ParseTargetError> {
+        target.check_channels(self.encoder.header())?;
+        let header = self.encoder.header();
+        if !header.value {
+            target.value = None;
+        }
+        if !header.ube {
+            target.ube = None;
+        }
+        if !header.policy {
+            target.policy = None;
+        }
+        if self.repair {
+            if !target.repair::<N, 4>()?.is_valid() {
+                self.repaired += 1;
+            }
+            return Ok(());
+        }
+        match target.validate::<N, 4>()?.error() {
+            None | Some(ParseTargetError::PolicyUnnormalized(_)) => Ok(()),
+            Some(err) => Err(err),
+        }
+    }
+
+    /// Map the policy onto the legal moves of the state.
+    fn align(&mut self, state: &Game<N, 4>, target: &mut Target) -> Result<(), AlignError> {
+        self.action_buffer.clear();
+        state.possible_moves(&mut self.action_buffer);
+        match target.align_policy(&self.action_buffer)? {
+            Alignment::Missing | Alignment::Exact => {}
+            Alignment::Reordered => self.reordered += 1,
+            Alignment::Filled(missing) => {
+                self.filled += 1;
+                self.filled_moves += missing;
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&self) {
+        if self.repaired > 0 {
+            println!("Repaired the policies of {} records.", self.repaired);
+        }
+        if self.reordered > 0 {
+            println!("Reordered the policies of {} records.", self.reordered);
+        }
+        if self.filled > 0 {
+            println!(
+                "Filled in {} missing moves in the policies of {} records.",
+                self.filled_moves, self.filled
+            );
+        }
+    }
+
+    fn write(
+        &mut self,
+        output: &mut impl Write,
+        state: &Game<N, 4>,
+        target: &Target,
+        original_size: usize,
+    ) -> Result<(), CliError> {
+        let i = self.records;
+
+        // stats
+        self.records += 1;
+        self.original_size += original_size;
+
+        // Write the state (relative / full) and the channels
+        let record = Record::from_target(state.clone(), target);
+        let this_written = self
+            .encoder
+            .write_record(output, &record)
+            .context("Could not write to the output file")?;
+        self.written += this_written;
+
+        if i.is_multiple_of(10_000) {
+            println!(
+                "[{i}] {} -> {} ({:.1}%)",
+                self.original_size,
+                self.written,
+                percent(self.original_size, self.written)
+            );
+        }
+        if cfg!(false) {
+            println!(
+                "[{i}] {original_size} -> {this_written} ({:.1}%), total: {} -> {} ({:.1}%).",
+                percent(original_size, this_written),
+                self.original_size,
+                self.written,
+                percent(self.original_size, self.written),
+            )
+        }
+        Ok(())
+    }
+}
+
+fn percent(before: usize, after: usize) -> f32 {
+    100.0 * (after as f32 / before as f32)
+}
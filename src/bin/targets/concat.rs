@@ -0,0 +1,84 @@
+use std::io::Write;
+
+use compress_targets::codec::{Decoder, Encoder};
+use fast_tak::Reserves;
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets concat <path/to/input>... --output <path/to/output> --size <size_of_board>
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size"];
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let inputs = args.rest();
+    args.finish()?;
+    if inputs.is_empty() {
+        return Err(CliError::Usage("Missing <path/to/input>".to_string()));
+    }
+
+    let mut output = create_output(&output_path)?;
+    match size {
+        3 => concat::<3>(&inputs, &mut output),
+        4 => concat::<4>(&inputs, &mut output),
+        5 => concat::<5>(&inputs, &mut output),
+        6 => concat::<6>(&inputs, &mut output),
+        7 => concat::<7>(&inputs, &mut output),
+        8 => concat::<8>(&inputs, &mut output),
+        _ => unreachable!("the size was checked"),
+    }?;
+    output
+        .flush()
+        .context("Could not write to the output file")?;
+    println!("Successfully concatenated targets.");
+    Ok(())
+}
+
+fn concat<const N: usize>(inputs: &[String], output: &mut impl Write) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut encoder = None;
+    for path in inputs {
+        let input = open_input(path)?;
+        let mut decoder = Decoder::<N, _>::new(input);
+        let header = decoder
+            .header()
+            .context(format_args!("Could not read the header of {path}"))?;
+
+        // All files must store the same channels, which the output declares once.
+        let encoder = match &mut encoder {
+            None => {
+                header
+                    .write(output)
+                    .context("Could not write to the output file")?;
+                encoder.insert(Encoder::<N>::with_header(header.clone()))
+            }
+            Some(encoder) if encoder.header() != header => {
+                return Err(CliError::Failed(format!(
+                    "{path} stores different channels than {}",
+                    inputs[0]
+                )));
+            }
+            Some(encoder) => encoder,
+        };
+
+        // Each file starts from its own state, so the first record must be full.
+        encoder.reset();
+        for i in 0.. {
+            let Some(record) = decoder
+                .read_record()
+                .context(format_args!("Could not decode record [{i}] of {path}"))?
+            else {
+                break;
+            };
+            encoder
+                .copy_record(output, &record, &decoder)
+                .context(format_args!("Could not write record [{i}] of {path}"))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use compress_targets::{
+    format::{write_records, Binary, Csv, JsonLines, OutputFormat, Text},
+    mapped::{MappedFile, SliceDecoder},
+    tensor::{NpzWriter, TensorWriter},
+};
+use fast_tak::Reserves;
+
+use crate::args::{Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets decompress <path/to/input> --size <size_of_board> [--format <format>] [--output <path/to/output>]
+
+Formats:
+    text      one `tps;value;policy` line per target (default)
+    text-ube  one `tps;value;ube;policy` line per target, as accepted by `Target::from_str`
+    jsonl     one JSON object per target
+    csv       comma-separated values with a header line
+    binary    fixed-layout little-endian records, see `src/format.rs`
+    tensors   raw little-endian arrays and a schema.json in the output directory
+    npz       NumPy arrays in the output directory, or in an archive if the output ends with .npz
+
+The text, jsonl, csv, and binary formats are written to standard output
+unless an output file is given. The tensors and npz formats require an output path.
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size", "--format"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    TextUbe,
+    JsonLines,
+    Csv,
+    Binary,
+    Tensors,
+    Npz,
+}
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output: Option<String> = args.option("--output")?;
+    let size = args.size()?;
+    let format = match args.option::<String>("--format")?.as_deref() {
+        None | Some("text") => Format::Text,
+        Some("text-ube") => Format::TextUbe,
+        Some("jsonl") => Format::JsonLines,
+        Some("csv") => Format::Csv,
+        Some("binary") => Format::Binary,
+        Some("tensors") => Format::Tensors,
+        Some("npz") => Format::Npz,
+        Some(name) => return Err(CliError::Usage(format!("Unknown format {name}"))),
+    };
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+    if matches!(format, Format::Tensors | Format::Npz) && output.is_none() {
+        return Err(CliError::Usage(
+            "The tensors and npz formats require --output".to_string(),
+        ));
+    }
+
+    let input = MappedFile::open(&input_path)
+        .context(format_args!("Could not open input file {input_path}"))?;
+    let output = output.as_deref();
+    match size {
+        3 => decompress::<3>(&input, format, output),
+        4 => decompress::<4>(&input, format, output),
+        5 => decompress::<5>(&input, format, output),
+        6 => decompress::<6>(&input, format, output),
+        7 => decompress::<7>(&input, format, output),
+        8 => decompress::<8>(&input, format, output),
+        _ => unreachable!("the size was checked"),
+    }?;
+    // Keep standard output clean when the targets are written there.
+    if output.is_some() {
+        println!("Successfully decompressed targets.");
+    } else {
+        eprintln!("Successfully decompressed targets.");
+    }
+    Ok(())
+}
+
+fn create_format<const N: usize>(
+    format: Format,
+    output: Option<&str>,
+) -> io::Result<Box<dyn OutputFormat<N>>>
+where
+    Reserves<N>: Default,
+{
+    if let Some(path) = output {
+        match format {
+            Format::Tensors => return Ok(Box::new(TensorWriter::<N>::create(path)?)),
+            Format::Npz => return Ok(Box::new(NpzWriter::<N>::create(path)?)),
+            _ => {}
+        }
+    }
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    Ok(match format {
+        Format::Text => Box::new(Text {
+            output: writer,
+            ube: false,
+        }),
+        Format::TextUbe => Box::new(Text {
+            output: writer,
+            ube: true,
+        }),
+        Format::JsonLines => Box::new(JsonLines { output: writer }),
+        Format::Csv => Box::new(Csv::new(writer)),
+        Format::Binary => Box::new(Binary::new(writer)),
+        Format::Tensors | Format::Npz => unreachable!("an output path is required"),
+    })
+}
+
+fn decompress<const N: usize>(
+    input: &[u8],
+    format: Format,
+    output: Option<&str>,
+) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let format = create_format::<N>(format, output).context("Could not create the output files")?;
+    let decoder = SliceDecoder::<N>::new(input).context("Could not read the header")?;
+    write_records(decoder, format).context("Could not decompress targets")?;
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{self, BufRead, Write},
+};
+
+use compress_targets::{
+    codec::{DecodeError, Decoder, Encoder, Record},
+    header::Header,
+    metadata::Metadata,
+    visits::Visits,
+};
+use fast_tak::{Board, Game, Reserves, Symmetry};
+use takparse::{Color, GameResult, Move};
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets dedupe <path/to/input> --output <path/to/output> --size <size_of_board> [--merge first|mean|visits] [--symmetry]
+
+Merges:
+    first   keep the first occurrence of a position (default)
+    mean    average the values, UBEs, and policies, or add up the visit counts
+    visits  like mean, but weight every occurrence by its total visits, which needs the visits channel
+
+With --symmetry, positions which are symmetries of each other are duplicates.
+Every position keeps the orientation of its first occurrence, into which the later ones are turned before merging.
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size", "--merge"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Merge {
+    /// Keep the first occurrence of a position.
+    First,
+    /// Average the values, UBEs, and policies of all occurrences,
+    /// or add up their visit counts, keeping the metadata and the game result of the first one.
+    Mean,
+    /// Like [`Merge::Mean`], but weight every occurrence by its total visits,
+    /// where an occurrence without visits counts as one.
+    Visits,
+}
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let merge = match args.option::<String>("--merge")?.as_deref() {
+        None | Some("first") => Merge::First,
+        Some("mean") => Merge::Mean,
+        Some("visits") => Merge::Visits,
+        Some(name) => return Err(CliError::Usage(format!("Unknown merge {name}"))),
+    };
+    let symmetry = args.flag("--symmetry");
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    let mut output = create_output(&output_path)?;
+    let result = match size {
+        3 => dedupe::<3>(input, &mut output, merge, symmetry),
+        4 => dedupe::<4>(input, &mut output, merge, symmetry),
+        5 => dedupe::<5>(input, &mut output, merge, symmetry),
+        6 => dedupe::<6>(input, &mut output, merge, symmetry),
+        7 => dedupe::<7>(input, &mut output, merge, symmetry),
+        8 => dedupe::<8>(input, &mut output, merge, symmetry),
+        _ => unreachable!("the size was checked"),
+    };
+    let (records, duplicates) = result
+        .and_then(|stats| Ok(output.flush().map(|()| stats)?))
+        .context("Could not deduplicate targets")?;
+    println!("Merged {duplicates} duplicates out of {records} records.");
+    println!("Successfully deduplicated targets.");
+    Ok(())
+}
+
+/// Weighted sums of the channels of a position. The channels which the header
+/// does not declare stay at zero.
+struct Merged<const N: usize> {
+    state: Game<N, 4>,
+    weight: f64,
+    value: f64,
+    ube: f64,
+    policy: Vec<(Move, f64)>,
+    total_visits: u32,
+    visits: Vec<(Move, u32)>,
+    metadata: Vec<(String, Metadata)>,
+    result: Option<GameResult>,
+}
+
+impl<const N: usize> Merged<N> {
+    fn add(&mut self, record: Record<N>, merge: Merge) {
+        let weight = match (merge, &record.visits) {
+            (Merge::Visits, Some(visits)) => f64::from(visits.total.max(1)),
+            _ => 1.0,
+        };
+        self.weight += weight;
+        self.value += weight * record.value.map_or(0.0, f64::from);
+        self.ube += weight * record.ube.map_or(0.0, f64::from);
+        for (action, probability) in record.policy.into_iter().flatten() {
+            let probability = weight * f64::from(probability);
+            match self.policy.iter_mut().find(|(a, _)| *a == action) {
+                Some((_, p)) => *p += probability,
+                None => self.policy.push((action, probability)),
+            }
+        }
+        let Some(visits) = record.visits else {
+            return;
+        };
+        self.total_visits = self.total_visits.saturating_add(visits.total);
+        for &(action, count) in visits.counts.iter() {
+            match self.visits.iter_mut().find(|(a, _)| *a == action) {
+                Some((_, c)) => *c = c.saturating_add(count),
+                None => self.visits.push((action, count)),
+            }
+        }
+    }
+
+    /// The weighted mean of every channel in the header, and the sum of the visit counts.
+    fn mean(self, header: &Header) -> Record<N> {
+        let mean = |sum: f64| (sum / self.weight) as f32;
+        let visits = header.visits.then(|| Visits {
+            total: self.total_visits,
+            counts: self.visits.into_boxed_slice(),
+        });
+        let policy = match &visits {
+            Some(visits) => Some(visits.policy().into_vec()),
+            None => header
+                .policy
+                .then(|| self.policy.into_iter().map(|(a, p)| (a, mean(p))).collect()),
+        };
+        Record {
+            action: None,
+            diff: false,
+            state: self.state,
+            value: header.value.then(|| mean(self.value)),
+            ube: header.ube.then(|| mean(self.ube)),
+            policy,
+            visits,
+            metadata: self.metadata,
+            result: self.result,
+        }
+    }
+}
+
+/// Returns the number of records and the number of duplicates.
+fn dedupe<const N: usize>(
+    input: impl BufRead,
+    output: &mut impl Write,
+    merge: Merge,
+    symmetry: bool,
+) -> Result<(usize, usize), DecodeError>
+where
+    Reserves<N>: Default,
+{
+    let mut decoder = Decoder::<N, _>::new(input);
+    let header = decoder.header()?.clone();
+    if merge == Merge::Visits && !header.visits {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the input has no visit counts to weight by",
+        )
+        .into());
+    }
+    header.write(output)?;
+    let mut encoder = Encoder::<N>::with_header(header);
+    let mut seen: HashMap<(Board<N>, Color), usize> = HashMap::new();
+    let mut merged: Vec<Merged<N>> = vec![];
+    let mut records = 0;
+
+    while let Some(mut record) = decoder.read_record()? {
+        records += 1;
+        let key = if symmetry {
+            canonical_board(&record.state)
+        } else {
+            record.state.board.clone()
+        };
+        let index = merged.len();
+        match seen.entry((key, record.state.to_move)) {
+            Entry::Occupied(entry) if merge != Merge::First => {
+                let position = &mut merged[*entry.get()];
+                if symmetry {
+                    orient(&mut record, &position.state.board);
+                }
+                position.add(record, merge);
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(entry) if merge != Merge::First => {
+                entry.insert(index);
+                let mut position = Merged {
+                    state: record.state.clone(),
+                    weight: 0.0,
+                    value: 0.0,
+                    ube: 0.0,
+                    policy: vec![],
+                    total_visits: 0,
+                    visits: vec![],
+                    metadata: record.metadata.clone(),
+                    result: record.result,
+                };
+                position.add(record, merge);
+                merged.push(position);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(index);
+                encoder.copy_record(output, &record, &decoder)?;
+            }
+        }
+    }
+
+    for merged in merged {
+        let record = merged.mean(encoder.header());
+        encoder.write_record(output, &record)?;
+    }
+
+    Ok((records, records - seen.len()))
+}
+
+/// The smallest board among the symmetries, which is the same for all of them.
+fn canonical_board<const N: usize>(state: &Game<N, 4>) -> Board<N> {
+    state
+        .symmetries()
+        .into_iter()
+        .map(|state| state.board)
+        .min()
+        .expect("There are always 8 symmetries")
+}
+
+/// Transform the record into the symmetry with the given board.
+fn orient<const N: usize>(record: &mut Record<N>, board: &Board<N>) {
+    let (index, state) = record
+        .state
+        .symmetries()
+        .into_iter()
+        .enumerate()
+        .find(|(_, state)| state.board == *board)
+        .expect("The board should be a symmetry of the record");
+    record.state = state;
+    for (action, _) in record.policy.iter_mut().flatten() {
+        *action = Symmetry::<N>::symmetries(action)[index];
+    }
+    for (action, _) in record
+        .visits
+        .iter_mut()
+        .flat_map(|visits| visits.counts.iter_mut())
+    {
+        *action = Symmetry::<N>::symmetries(action)[index];
+    }
+}
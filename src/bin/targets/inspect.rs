@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead},
+};
+
+use compress_targets::{
+    codec::{quantize_probability, Decoder},
+    context_model::PolicyEncoder,
+};
+use fast_tak::Reserves;
+
+use crate::args::{open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets inspect <path/to/input> --size <size_of_board>
+
+Reports the channels, the record count, how the bytes are spent, and histograms
+of the policy sizes, values, game lengths, and board fill.
+A game is a full state and the relative records after it, which are actions or diffs,
+while `targets to-ptn` also starts a new game at every diff, since no moves lead to it.
+The quantized policy probabilities are also coded with the context model
+of `compress_targets::context_model`, and its size is compared to their fixed 16-bit codes.
+Visit counts are compared to their size as plain varints.
+";
+
+pub const OPTIONS: &[&str] = &["--size"];
+
+/// Width of the longest bar in a histogram.
+const BAR_WIDTH: u64 = 40;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let size = args.size()?;
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    match size {
+        3 => inspect::<3>(input),
+        4 => inspect::<4>(input),
+        5 => inspect::<5>(input),
+        6 => inspect::<6>(input),
+        7 => inspect::<7>(input),
+        8 => inspect::<8>(input),
+        _ => unreachable!("the size was checked"),
+    }
+}
+
+/// Counts of values grouped into buckets of equal width.
+struct Histogram {
+    name: &'static str,
+    width: f64,
+    buckets: BTreeMap<i64, u64>,
+}
+
+impl Histogram {
+    fn new(name: &'static str, width: f64) -> Self {
+        Self {
+            name,
+            width,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        *self
+            .buckets
+            .entry((x / self.width).floor() as i64)
+            .or_default() += 1;
+    }
+
+    fn print(&self) {
+        println!("\n{}:", self.name);
+        let (Some((&first, _)), Some((&last, _))) = (
+            self.buckets.first_key_value(),
+            self.buckets.last_key_value(),
+        ) else {
+            println!("  (empty)");
+            return;
+        };
+        let max = self.buckets.values().copied().max().unwrap_or(1);
+        for bucket in first..=last {
+            let count = self.buckets.get(&bucket).copied().unwrap_or(0);
+            let start = bucket as f64 * self.width;
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max) as usize);
+            println!(
+                "  [{start:>7.2}, {:>7.2}) {count:>10} {bar}",
+                start + self.width
+            );
+        }
+    }
+}
+
+/// The number of bytes of a count as a varint with 7 bits per byte.
+fn varint_len(count: u32) -> u64 {
+    u64::from((u32::BITS - count.leading_zeros()).div_ceil(7).max(1))
+}
+
+fn inspect<const N: usize>(input: impl BufRead) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut records = 0u64;
+    let mut full = 0u64;
+    let mut diffs = 0u64;
+    let mut action_bytes = 0u64;
+    let mut state_bytes = 0u64;
+    let mut value_bytes = 0u64;
+    let mut metadata_bytes = 0u64;
+    let mut policy_bytes = 0u64;
+
+    let mut policy_sizes = Histogram::new("Kept policy moves per record", 5.0);
+    let mut values = Histogram::new("Values", 0.1);
+    let mut game_lengths = Histogram::new("Records per game", 10.0);
+    let mut board_fill = Histogram::new("Occupied squares (%)", 10.0);
+    let mut game_length = 0;
+    // One model for the whole file, which is coded but not stored.
+    let mut context_model = PolicyEncoder::new(io::sink());
+    let mut policy_symbols = 0u64;
+    let mut varint_bytes = 0u64;
+
+    // The statistics are still reported for the records before an error.
+    let mut failure = None;
+    let mut decoder = Decoder::<N, _>::new(input);
+    let header = decoder
+        .header()
+        .context("Could not read the header")?
+        .clone();
+    // The value is stored in 16 bits and the UBE as an `f32`.
+    let values_per_record = 2 * u64::from(header.value) + 4 * u64::from(header.ube);
+    loop {
+        let record = match decoder.read_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(err) => {
+                failure = Some(format!("Could not decode record [{records}]: {err}"));
+                break;
+            }
+        };
+        records += 1;
+
+        let state_header = (decoder.raw().len() - decoder.payload().len()) as u64;
+        if record.action.is_some() {
+            action_bytes += state_header;
+            game_length += 1;
+        } else if record.diff {
+            diffs += 1;
+            // Two bytes mark the diff and count the changed squares.
+            action_bytes += 2;
+            state_bytes += state_header - 2;
+            game_length += 1;
+        } else {
+            full += 1;
+            // One byte marks the state as full, and the game result follows it.
+            let result_bytes = u64::from(header.result);
+            action_bytes += 1;
+            state_bytes += state_header - 1 - result_bytes;
+            metadata_bytes += result_bytes;
+            if game_length > 0 {
+                game_lengths.add(game_length as f64);
+            }
+            game_length = 1;
+        }
+        let record_metadata_bytes = decoder.metadata().len() as u64;
+        value_bytes += values_per_record;
+        metadata_bytes += record_metadata_bytes;
+        policy_bytes += decoder.payload().len() as u64 - values_per_record - record_metadata_bytes;
+
+        if let Some(policy) = &record.policy {
+            policy_sizes.add(policy.len() as f64);
+        }
+        let probabilities = record.policy.iter().filter(|_| !header.visits).flatten();
+        for &(action, probability) in probabilities {
+            let symbol = quantize_probability(probability).context("Could not code the policy")?;
+            if let Some(symbol) = symbol {
+                policy_symbols += 1;
+                context_model
+                    .encode(action, symbol)
+                    .context("Could not code the policy")?;
+            }
+        }
+        if let Some(visits) = &record.visits {
+            // The total, and every visited move with its count, and the terminator.
+            varint_bytes += varint_len(visits.total) + 1;
+            for &(_, count) in visits.counts.iter() {
+                varint_bytes += 2 + varint_len(count);
+            }
+        }
+        if let Some(value) = record.value {
+            values.add(value.into());
+        }
+        let occupied = record
+            .state
+            .board
+            .iter()
+            .flatten()
+            .filter(|stack| stack.top().is_some())
+            .count();
+        board_fill.add(100.0 * occupied as f64 / (N * N) as f64);
+    }
+    if game_length > 0 {
+        game_lengths.add(game_length as f64);
+    }
+
+    let total = action_bytes + state_bytes + value_bytes + metadata_bytes + policy_bytes;
+    let percent = |part: u64| 100.0 * part as f64 / total.max(1) as f64;
+    let channels: Vec<String> = [
+        (header.value, "value"),
+        (header.ube, "ube"),
+        (header.policy && !header.visits, "policy"),
+        (header.visits, "visits"),
+        (header.result, "result"),
+    ]
+    .into_iter()
+    .filter(|&(present, _)| present)
+    .map(|(_, name)| name.to_string())
+    .chain(
+        header
+            .columns
+            .iter()
+            .map(|column| format!("{}:{}", column.name, column.kind)),
+    )
+    .collect();
+    println!("Channels: {}", channels.join(", "));
+    println!("Records: {records}");
+    println!(
+        "Full states: {full} ({:.1}%), relative states: {} ({:.1}%), of which diffs: {diffs} ({:.1}%)",
+        100.0 * full as f64 / records.max(1) as f64,
+        records - full,
+        100.0 * (records - full) as f64 / records.max(1) as f64,
+        100.0 * diffs as f64 / records.max(1) as f64,
+    );
+    println!(
+        "Bytes: {total} ({:.1} per record)",
+        total as f64 / records.max(1) as f64
+    );
+    for (name, bytes) in [
+        ("actions", action_bytes),
+        ("states", state_bytes),
+        ("values", value_bytes),
+        ("metadata", metadata_bytes),
+        ("policies", policy_bytes),
+    ] {
+        println!("  {name:<8} {bytes:>12} ({:.1}%)", percent(bytes));
+    }
+    if header.policy && !header.visits {
+        context_model
+            .finish()
+            .context("Could not code the policy")?;
+        let fixed = 2 * policy_symbols;
+        let modeled = context_model.written() as u64;
+        println!(
+            "Policy probabilities: {policy_symbols}, {fixed} bytes as 16-bit codes, {modeled} bytes with the context model ({:.1}%)",
+            100.0 * modeled as f64 / fixed.max(1) as f64
+        );
+    }
+
+    if header.visits {
+        println!(
+            "Visit counts: {policy_bytes} bytes, {varint_bytes} bytes as varints ({:.1}%)",
+            100.0 * policy_bytes as f64 / varint_bytes.max(1) as f64
+        );
+    }
+
+    if header.policy {
+        policy_sizes.print();
+    }
+    if header.value {
+        values.print();
+    }
+    game_lengths.print();
+    board_fill.print();
+    failure.map_or(Ok(()), |message| Err(CliError::Failed(message)))
+}
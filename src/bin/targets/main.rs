@@ -0,0 +1,148 @@
+use std::process::ExitCode;
+
+use args::{Args, CliError};
+
+mod args;
+mod check;
+mod compress;
+mod concat;
+mod decompress;
+mod dedupe;
+mod inspect;
+mod reverse;
+mod shuffle;
+mod slice;
+mod split;
+mod to_ptn;
+
+const USAGE: &str = "Usage:
+    targets <command> [arguments...]
+
+Commands:
+    compress    compress text, JSON Lines, or PTN targets
+    decompress  write compressed targets as text, JSON Lines, CSV, binary, or arrays
+    check       compare original targets with converted ones
+    inspect     report statistics about a compressed file
+    reverse     reverse the lines of a text file
+    concat      join compressed files
+    split       split a compressed file into parts
+    slice       cut a range of records out of a compressed file
+    shuffle     shuffle the records of a compressed file
+    dedupe      merge repeated positions of a compressed file
+    to-ptn      reconstruct PTN games from a compressed file
+
+Run `targets <command> --help` for the arguments of a command.
+";
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    /// The options which take a value, so that their values are not taken for `--help`.
+    options: &'static [&'static str],
+    run: fn(Args) -> Result<(), CliError>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "compress",
+        usage: compress::USAGE,
+        options: compress::OPTIONS,
+        run: compress::run,
+    },
+    Command {
+        name: "decompress",
+        usage: decompress::USAGE,
+        options: decompress::OPTIONS,
+        run: decompress::run,
+    },
+    Command {
+        name: "check",
+        usage: check::USAGE,
+        options: check::OPTIONS,
+        run: check::run,
+    },
+    Command {
+        name: "inspect",
+        usage: inspect::USAGE,
+        options: inspect::OPTIONS,
+        run: inspect::run,
+    },
+    Command {
+        name: "reverse",
+        usage: reverse::USAGE,
+        options: reverse::OPTIONS,
+        run: reverse::run,
+    },
+    Command {
+        name: "concat",
+        usage: concat::USAGE,
+        options: concat::OPTIONS,
+        run: concat::run,
+    },
+    Command {
+        name: "split",
+        usage: split::USAGE,
+        options: split::OPTIONS,
+        run: split::run,
+    },
+    Command {
+        name: "slice",
+        usage: slice::USAGE,
+        options: slice::OPTIONS,
+        run: slice::run,
+    },
+    Command {
+        name: "shuffle",
+        usage: shuffle::USAGE,
+        options: shuffle::OPTIONS,
+        run: shuffle::run,
+    },
+    Command {
+        name: "dedupe",
+        usage: dedupe::USAGE,
+        options: dedupe::OPTIONS,
+        run: dedupe::run,
+    },
+    Command {
+        name: "to-ptn",
+        usage: to_ptn::USAGE,
+        options: to_ptn::OPTIONS,
+        run: to_ptn::run,
+    },
+];
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let name = match args.next() {
+        Some(name) if !matches!(name.as_str(), "--help" | "-h" | "help") => name,
+        Some(_) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        None => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+        eprintln!("Unknown command {name}\n\n{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let args = Args::new(args.collect(), command.options);
+    if args.help() {
+        println!("{}", command.usage);
+        return ExitCode::SUCCESS;
+    }
+    match (command.run)(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("{message}\n\n{}", command.usage);
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
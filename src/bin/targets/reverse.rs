@@ -0,0 +1,28 @@
+use std::io::{BufRead, Write};
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets reverse <path/to/input> --output <path/to/output>
+";
+
+pub const OPTIONS: &[&str] = &["--output"];
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    let mut output = create_output(&output_path)?;
+
+    let lines: Vec<String> = input
+        .lines()
+        .collect::<Result<_, _>>()
+        .context("Could not read input file")?;
+
+    for line in lines.into_iter().rev() {
+        writeln!(output, "{line}").context("Could not write to the output file")?;
+    }
+    output.flush().context("Could not write to the output file")
+}
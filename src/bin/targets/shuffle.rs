@@ -0,0 +1,197 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Write},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use compress_targets::codec::{DecodeError, Decoder, Encoder};
+use fast_tak::Reserves;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::args::{create_output, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets shuffle <path/to/input> --output <path/to/output> --size <size_of_board> [--seed <seed>] [--memory <megabytes>]
+
+The memory bound is approximate: it counts the records once they are written with full states,
+and their offsets, but not the buffers of the input and the output.
+Records are scattered into temporary files next to the output until they fit,
+and the temporary files are removed afterwards, also if shuffling fails.
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size", "--seed", "--memory"];
+
+/// Maximum number of temporary files which are open at the same time.
+const MAX_BUCKETS: u64 = 256;
+/// Stop splitting into buckets if the records are somehow still too large.
+const MAX_DEPTH: usize = 4;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let seed = args.option("--seed")?.unwrap_or(0);
+    let megabytes: u64 = args.option("--memory")?.unwrap_or(1024);
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let mut output = create_output(&output_path)?;
+    let mut shuffler = Shuffler {
+        rng: StdRng::seed_from_u64(seed),
+        memory: megabytes.max(1) * 1024 * 1024,
+        temp_prefix: format!("{output_path}.shuffle"),
+    };
+    let input = Path::new(&input_path);
+    let result = match size {
+        3 => shuffler.shuffle::<3>(input, &mut output, 0),
+        4 => shuffler.shuffle::<4>(input, &mut output, 0),
+        5 => shuffler.shuffle::<5>(input, &mut output, 0),
+        6 => shuffler.shuffle::<6>(input, &mut output, 0),
+        7 => shuffler.shuffle::<7>(input, &mut output, 0),
+        8 => shuffler.shuffle::<8>(input, &mut output, 0),
+        _ => unreachable!("the size was checked"),
+    };
+    result
+        .and_then(|()| Ok(output.flush()?))
+        .context("Could not shuffle targets")?;
+    println!("Successfully shuffled targets with seed {seed}.");
+    Ok(())
+}
+
+/// External-memory shuffle.
+///
+/// Records are scattered into randomly chosen temporary files until each file
+/// fits in memory, and then every file is shuffled in memory and appended to the output.
+/// All records are written with full states, since their neighbours change.
+/// A diff would rarely be smaller, because shuffled neighbours are unrelated positions.
+/// Every temporary file gets the header of the input, so that it can be decoded on its own.
+struct Shuffler {
+    rng: StdRng,
+    memory: u64,
+    temp_prefix: String,
+}
+
+impl Shuffler {
+    fn shuffle<const N: usize>(
+        &mut self,
+        path: &Path,
+        output: &mut impl Write,
+        depth: usize,
+    ) -> Result<(), DecodeError>
+    where
+        Reserves<N>: Default,
+    {
+        let len = fs::metadata(path)?.len();
+        let open = || -> Result<_, DecodeError> {
+            let mut decoder = Decoder::<N, _>::new(BufReader::new(File::open(path)?));
+            decoder.header()?;
+            Ok(decoder)
+        };
+        let mut decoder = open()?;
+        let header = decoder.header()?.clone();
+        if depth == 0 {
+            header.write(output)?;
+        }
+        let mut encoder = Encoder::<N>::with_header(header);
+
+        if len <= self.memory || depth >= MAX_DEPTH {
+            let limit = (depth < MAX_DEPTH).then_some(self.memory);
+            if let Some(mut loaded) = Loaded::read(&mut decoder, &mut encoder, limit)? {
+                loaded.records.shuffle(&mut self.rng);
+                for range in loaded.records {
+                    output.write_all(&loaded.bytes[range])?;
+                }
+                return Ok(());
+            }
+            // The records grew too large once they were written with full states.
+            decoder = open()?;
+        }
+
+        let buckets = len
+            .div_ceil(self.memory)
+            .saturating_mul(2)
+            .clamp(2, MAX_BUCKETS);
+        let paths = TempFiles(
+            (0..buckets)
+                .map(|i| format!("{}.{depth}.{i}.tmp", self.temp_prefix).into())
+                .collect(),
+        );
+        let mut files = paths
+            .0
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .map(BufWriter::new)
+                    .and_then(|mut file| {
+                        encoder.header().write(&mut file)?;
+                        Ok(file)
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        while let Some(record) = decoder.read_record()? {
+            let bucket = self.rng.gen_range(0..files.len());
+            let bucket = &mut files[bucket];
+            encoder.reset();
+            encoder.copy_record(bucket, &record, &decoder)?;
+        }
+        for mut file in files {
+            file.flush()?;
+        }
+
+        for path in &paths.0 {
+            self.shuffle::<N>(path, output, depth + 1)?;
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Records with full states, which are shuffled in memory.
+struct Loaded {
+    bytes: Vec<u8>,
+    /// The range of every record in the bytes.
+    records: Vec<Range<usize>>,
+}
+
+impl Loaded {
+    /// Read all records, or `None` if they take more than `limit` bytes of memory.
+    fn read<const N: usize>(
+        decoder: &mut Decoder<N, BufReader<File>>,
+        encoder: &mut Encoder<N>,
+        limit: Option<u64>,
+    ) -> Result<Option<Self>, DecodeError>
+    where
+        Reserves<N>: Default,
+    {
+        let mut bytes = vec![];
+        let mut records = vec![];
+        while let Some(record) = decoder.read_record()? {
+            let start = bytes.len();
+            encoder.reset();
+            encoder.copy_record(&mut bytes, &record, decoder)?;
+            records.push(start..bytes.len());
+            let used = bytes.capacity() + records.capacity() * mem::size_of::<Range<usize>>();
+            if limit.is_some_and(|limit| used as u64 > limit) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(Self { bytes, records }))
+    }
+}
+
+/// Temporary files which are removed when they go out of scope, also after an error.
+struct TempFiles(Vec<PathBuf>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            // Files which were already removed or never created are fine.
+            let _ = fs::remove_file(path);
+        }
+    }
+}
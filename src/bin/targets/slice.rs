@@ -0,0 +1,75 @@
+use std::io::{BufRead, Write};
+
+use compress_targets::codec::{Decoder, Encoder};
+use fast_tak::Reserves;
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets slice <path/to/input> --output <path/to/output> --size <size_of_board> [--from <first_record>] [--to <end_record>]
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size", "--from", "--to"];
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let from = args.option("--from")?.unwrap_or(0);
+    let to = args.option("--to")?.unwrap_or(usize::MAX);
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    let mut output = create_output(&output_path)?;
+    match size {
+        3 => slice::<3>(input, &mut output, from, to),
+        4 => slice::<4>(input, &mut output, from, to),
+        5 => slice::<5>(input, &mut output, from, to),
+        6 => slice::<6>(input, &mut output, from, to),
+        7 => slice::<7>(input, &mut output, from, to),
+        8 => slice::<8>(input, &mut output, from, to),
+        _ => unreachable!("the size was checked"),
+    }?;
+    output
+        .flush()
+        .context("Could not write to the output file")?;
+    println!("Successfully sliced targets.");
+    Ok(())
+}
+
+/// Copy the records in `from..to` to the output.
+fn slice<const N: usize>(
+    input: impl BufRead,
+    output: &mut impl Write,
+    from: usize,
+    to: usize,
+) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut decoder = Decoder::<N, _>::new(input);
+    let header = decoder
+        .header()
+        .context("Could not read the header")?
+        .clone();
+    header
+        .write(output)
+        .context("Could not write to the output file")?;
+    let mut encoder = Encoder::<N>::with_header(header);
+
+    for i in 0..to {
+        let Some(record) = decoder
+            .read_record()
+            .context(format_args!("Could not decode record [{i}]"))?
+        else {
+            break;
+        };
+        if i < from {
+            continue;
+        }
+        encoder
+            .copy_record(output, &record, &decoder)
+            .context(format_args!("Could not write record [{i}]"))?;
+    }
+    Ok(())
+}
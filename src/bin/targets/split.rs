@@ -0,0 +1,89 @@
+use std::io::{BufRead, Write};
+
+use compress_targets::codec::{Decoder, Encoder};
+use fast_tak::Reserves;
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets split <path/to/input> --output <path/to/output_prefix> --size <size_of_board> --records <records_per_file>
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size", "--records"];
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let prefix: String = args.required("--output")?;
+    let size = args.size()?;
+    let records: usize = args.required("--records")?;
+    if records == 0 {
+        return Err(CliError::Usage(
+            "The number of records per file must be positive".to_string(),
+        ));
+    }
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    match size {
+        3 => split::<3>(input, &prefix, records),
+        4 => split::<4>(input, &prefix, records),
+        5 => split::<5>(input, &prefix, records),
+        6 => split::<6>(input, &prefix, records),
+        7 => split::<7>(input, &prefix, records),
+        8 => split::<8>(input, &prefix, records),
+        _ => unreachable!("the size was checked"),
+    }?;
+    println!("Successfully split targets.");
+    Ok(())
+}
+
+fn split<const N: usize>(input: impl BufRead, prefix: &str, records: usize) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut decoder = Decoder::<N, _>::new(input);
+    let header = decoder
+        .header()
+        .context("Could not read the header")?
+        .clone();
+    let mut encoder = Encoder::<N>::with_header(header);
+    let mut output = None;
+    let mut files = 0;
+
+    for i in 0.. {
+        let Some(record) = decoder
+            .read_record()
+            .context(format_args!("Could not decode record [{i}]"))?
+        else {
+            break;
+        };
+
+        // Start a new file with a full state.
+        if i % records == 0 {
+            if let Some(mut previous) = output.take() {
+                Write::flush(&mut previous).context("Could not write to the output file")?;
+            }
+            let mut next = create_output(&format!("{prefix}.{files}.bin"))?;
+            encoder
+                .header()
+                .write(&mut next)
+                .context("Could not write to the output file")?;
+            output = Some(next);
+            encoder.reset();
+            files += 1;
+        }
+
+        let output = output.as_mut().expect("The output file should be open");
+        encoder
+            .copy_record(output, &record, &decoder)
+            .context(format_args!("Could not write record [{i}]"))?;
+    }
+
+    if let Some(output) = output.as_mut() {
+        output
+            .flush()
+            .context("Could not write to the output file")?;
+    }
+    println!("Wrote {files} files.");
+    Ok(())
+}
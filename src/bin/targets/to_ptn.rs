@@ -0,0 +1,133 @@
+use std::io::{BufRead, Write};
+
+use compress_targets::codec::{Decoder, Record};
+use fast_tak::{Game, Reserves};
+use takparse::{GameResult, Move, Ptn, Tag, Tps};
+
+use crate::args::{create_output, open_input, Args, CliError, Context};
+
+pub const USAGE: &str = "Usage:
+    targets to-ptn <path/to/input> --output <path/to/output> --size <size_of_board>
+
+Writes one PTN game for every record with a full state or a diff which is followed by relative records.
+Each move is annotated with the value of the position after it, if the file stores values.
+The game gets the result which the file stores, or else the result of its last position.
+";
+
+pub const OPTIONS: &[&str] = &["--output", "--size"];
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let output_path: String = args.required("--output")?;
+    let size = args.size()?;
+    let input_path = args.positional("<path/to/input>")?;
+    args.finish()?;
+
+    let input = open_input(&input_path)?;
+    let mut output = create_output(&output_path)?;
+    let result = match size {
+        3 => to_ptn::<3>(input, &mut output),
+        4 => to_ptn::<4>(input, &mut output),
+        5 => to_ptn::<5>(input, &mut output),
+        6 => to_ptn::<6>(input, &mut output),
+        7 => to_ptn::<7>(input, &mut output),
+        8 => to_ptn::<8>(input, &mut output),
+        _ => unreachable!("the size was checked"),
+    };
+    // The games before a decoding error are still written.
+    output
+        .flush()
+        .context("Could not write to the output file")?;
+    result?;
+    println!("Successfully reconstructed games.");
+    Ok(())
+}
+
+/// A run of records which starts with a full state or a diff.
+struct Run<const N: usize> {
+    start: Game<N, 4>,
+    start_value: Option<f32>,
+    moves: Vec<Move>,
+    values: Vec<Option<f32>>,
+    end: Game<N, 4>,
+    result: Option<GameResult>,
+}
+
+impl<const N: usize> Run<N>
+where
+    Reserves<N>: Default,
+{
+    fn new(record: Record<N>) -> Self {
+        Self {
+            start: record.state.clone(),
+            start_value: record.value,
+            moves: vec![],
+            values: vec![],
+            end: record.state,
+            result: record.result,
+        }
+    }
+
+    fn to_ptn(&self) -> Ptn {
+        let mut tags = vec![Tag::new("Size", N.to_string()), Tag::new("Komi", "2")];
+        if self.start.board != Game::<N, 4>::default().board {
+            tags.push(Tag::new("TPS", Tps::from(self.start.clone()).to_string()));
+        }
+        let comments = std::iter::once(self.start_value)
+            .chain(self.values.iter().copied())
+            .map(|value| value.iter().map(f32::to_string).collect())
+            .collect();
+        let result = self
+            .result
+            .or_else(|| GameResult::try_from(self.end.result()).ok());
+        Ptn::new(tags, self.moves.clone(), comments, result)
+    }
+}
+
+fn to_ptn<const N: usize>(input: impl BufRead, output: &mut impl Write) -> Result<(), CliError>
+where
+    Reserves<N>: Default,
+{
+    let mut games = 0;
+    let mut lone_positions = 0;
+    let mut write = |run: Run<N>, output: &mut dyn Write| {
+        if run.moves.is_empty() {
+            lone_positions += 1;
+            return Ok(());
+        }
+        games += 1;
+        if games > 1 {
+            writeln!(output)?;
+        }
+        write!(output, "{}", run.to_ptn())
+    };
+
+    let mut failure = None;
+    let mut run: Option<Run<N>> = None;
+    for (i, record) in Decoder::<N, _>::new(input).enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                failure = Some(format!("Could not decode record [{i}]: {err}"));
+                break;
+            }
+        };
+        if let (Some(action), Some(run)) = (record.action, &mut run) {
+            run.moves.push(action);
+            run.values.push(record.value);
+            run.end = record.state;
+            continue;
+        }
+        if let Some(previous) = run.replace(Run::new(record)) {
+            write(previous, output).context("Could not write to the output file")?;
+        }
+    }
+    if let Some(run) = run {
+        write(run, output).context("Could not write to the output file")?;
+    }
+
+    println!("Wrote {games} games.");
+    if lone_positions > 0 {
+        println!("Skipped {lone_positions} positions which are not followed by any moves.");
+    }
+    failure.map_or(Ok(()), |message| Err(CliError::Failed(message)))
+}
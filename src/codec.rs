@@ -0,0 +1,1379 @@
+//! Binary encoding of targets.
+//!
+//! A compressed file is a sequence of records. Each record starts with an
+//! action. If the action is the zero byte then a full state follows,
+//! otherwise the state is reached by playing the action on the previous one.
+//! After the state come the channels which the [`Header`] declares:
+//! by default the value and the policy. The policy is a list of
+//! actions and quantized log-probabilities terminated by a zero byte,
+//! or the visit counts of the search, see [`crate::visits`].
+//! The cells of the metadata columns come last, see [`crate::metadata`].
+//!
+//! If the header declares the result channel, a full state is followed by a byte
+//! with the final result of the game, which holds for the relative records after it.
+//! A record whose game has a different result than the previous one is therefore
+//! stored with its full state.
+//!
+//! A state which no single action reaches from the previous one may instead be stored
+//! as a diff: the bytes `0xFF` and the number of changed squares (a placement without
+//! a piece), followed by the side to move and the index and new stack of each changed square.
+//! The encoder writes whichever of the diff and the full state is smaller.
+//! Like relative actions, a diff keeps the metadata and the result of the previous record,
+//! so only full states start a block which can be decoded on its own.
+
+use std::io::{self, BufRead, Read, Write};
+
+use bitvec::{order::Lsb0, vec::BitVec};
+use fast_tak::{Board, Colors, Game, PlayError, Reserves, Stack};
+use takparse::{
+    Color, Direction, GameResult, Move, MoveKind, Pattern, Piece, Square, Tps, WinReason,
+};
+use thiserror::Error;
+
+use crate::{
+    header::{Header, MAGIC},
+    metadata::{Metadata, MetadataCoder},
+    visits::Visits,
+    Target, LOG_MIN, MIN_PROBABILITY,
+};
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("invalid action encoding {0:#04x} {1:#04x}")]
+    InvalidAction(u8, u8),
+    #[error("invalid stack size {0}")]
+    InvalidStackSize(u8),
+    #[error("relative action {0} cannot be played: {1}")]
+    IllegalAction(Move, PlayError),
+    #[error("invalid header: {0}")]
+    InvalidHeader(&'static str),
+    #[error("invalid metadata: {0}")]
+    InvalidMetadata(&'static str),
+    #[error("invalid game result {0:#04x}")]
+    InvalidResult(u8),
+    #[error("invalid square index {0}")]
+    InvalidSquare(u8),
+    #[error("{0:?} has more pieces on the board than in the reserves")]
+    TooManyPieces(Color),
+}
+
+/// A single decoded record.
+pub struct Record<const N: usize> {
+    /// The action which leads from the previous state to this one,
+    /// or `None` if the state was stored in full or as a diff.
+    pub action: Option<Move>,
+    /// Whether the state was stored as a diff of the previous one.
+    pub diff: bool,
+    pub state: Game<N, 4>,
+    pub value: Option<f32>,
+    pub ube: Option<f32>,
+    /// Only the actions which were kept during compression.
+    /// With visit counts, the counts normalized by their sum.
+    pub policy: Option<Vec<(Move, f32)>>,
+    /// Only the actions which were visited.
+    pub visits: Option<Visits>,
+    /// The cells which are present, in the order of [`Header::columns`].
+    pub metadata: Vec<(String, Metadata)>,
+    /// The final result of the game, if it is known.
+    pub result: Option<GameResult>,
+}
+
+impl<const N: usize> Record<N> {
+    /// A record with a value and a policy, which are the default channels.
+    pub fn new(state: Game<N, 4>, value: f32, policy: Vec<(Move, f32)>) -> Self {
+        Self {
+            action: None,
+            diff: false,
+            state,
+            value: Some(value),
+            ube: None,
+            policy: Some(policy),
+            visits: None,
+            metadata: vec![],
+            result: None,
+        }
+    }
+
+    /// Record with the targets of a [`Target`] for its state.
+    pub fn from_target(state: Game<N, 4>, target: &Target) -> Self {
+        Self {
+            action: None,
+            diff: false,
+            state,
+            value: target.value,
+            ube: target.ube,
+            policy: target.policy.as_deref().map(<[_]>::to_vec),
+            visits: target.visits.clone(),
+            metadata: target.metadata.clone(),
+            result: target.result,
+        }
+    }
+
+    /// Whether the state was stored in full, so that decoding can start at this record.
+    pub fn is_keyframe(&self) -> bool {
+        self.action.is_none() && !self.diff
+    }
+
+    /// Policy over all possible moves, where the actions which were dropped
+    /// during compression get [`MIN_PROBABILITY`], and unvisited actions get zero.
+    /// Like [`Visits::policy`], the policy is all zeros if nothing was visited.
+    pub fn completed_policy(&self) -> Option<Vec<(Move, f32)>> {
+        let policy = self.policy.as_ref()?;
+        let dropped = if self.visits.is_some() {
+            0.0
+        } else {
+            MIN_PROBABILITY as f32
+        };
+        let mut actions = vec![];
+        self.state.possible_moves(&mut actions);
+        let mut completed_policy: Vec<_> = actions
+            .into_iter()
+            .map(|a| match policy.iter().find(|(b, _)| *b == a) {
+                Some(&x) => x,
+                None => (a, dropped),
+            })
+            .collect();
+        let sum: f32 = completed_policy.iter().map(|(_, p)| p).sum();
+        if sum > 0.0 {
+            completed_policy.iter_mut().for_each(|(_, p)| *p /= sum);
+        }
+        Some(completed_policy)
+    }
+
+    pub fn to_target(&self) -> Target {
+        Target {
+            tps: Tps::from(self.state.clone()),
+            value: self.value,
+            ube: self.ube,
+            policy: self
+                .completed_policy()
+                .filter(|policy| !policy.is_empty())
+                .map(|policy| policy.into_boxed_slice()),
+            visits: self.visits.clone(),
+            metadata: self.metadata.clone(),
+            result: self.result,
+        }
+    }
+}
+
+/// Reads records one by one, keeping track of the state for relative records.
+pub struct Decoder<const N: usize, R> {
+    input: R,
+    header: Option<Header>,
+    reader: RecordReader<N>,
+    /// The metadata coder before the last record.
+    previous_metadata: MetadataCoder,
+    raw: Vec<u8>,
+    offset: u64,
+}
+
+impl<const N: usize, R: BufRead> Decoder<N, R>
+where
+    Reserves<N>: Default,
+{
+    /// Decoder for a whole file, which reads the header if there is one.
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            header: None,
+            reader: RecordReader::new(),
+            previous_metadata: MetadataCoder::default(),
+            raw: vec![],
+            offset: 0,
+        }
+    }
+
+    /// Decoder for an input which starts at a record with a full state,
+    /// such as a block of a file with this header.
+    pub fn with_header(input: R, header: Header) -> Self {
+        Self {
+            header: Some(header),
+            ..Self::new(input)
+        }
+    }
+
+    /// The header of the file. Its bytes count as the raw bytes before the first record.
+    pub fn header(&mut self) -> Result<&Header, DecodeError> {
+        if self.header.is_none() {
+            let header = if self.input.fill_buf()?.first() == Some(&MAGIC[0]) {
+                Header::read(&mut Recorder {
+                    input: &mut self.input,
+                    raw: &mut self.raw,
+                })?
+            } else {
+                Header::default()
+            };
+            self.header = Some(header);
+        }
+        Ok(self.header.as_ref().expect("The header was just read"))
+    }
+
+    /// Read the next record, or `None` at the end of the input.
+    pub fn read_record(&mut self) -> Result<Option<Record<N>>, DecodeError> {
+        self.header()?;
+        if self.input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.offset += self.raw.len() as u64;
+        self.raw.clear();
+        let mut input = Recorder {
+            input: &mut self.input,
+            raw: &mut self.raw,
+        };
+
+        let header = self.header.as_ref().expect("The header was read");
+        self.previous_metadata.clone_from(&self.reader.metadata);
+        self.reader.read(&mut input, header).map(Some)
+    }
+}
+
+impl<const N: usize, R> Decoder<N, R> {
+    /// Byte offset of the last record in the input.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The raw bytes of the last record.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The raw bytes of the channels of the last record, including its metadata
+    /// but not the result, which is stored with the full state.
+    pub fn payload(&self) -> &[u8] {
+        &self.raw[self.reader.payload_start..]
+    }
+
+    /// The raw bytes of the metadata of the last record.
+    pub fn metadata(&self) -> &[u8] {
+        &self.raw[self.reader.metadata_start..]
+    }
+}
+
+impl<const N: usize, R: BufRead> Iterator for Decoder<N, R>
+where
+    Reserves<N>: Default,
+{
+    type Item = Result<Record<N>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Keeps a copy of every byte read so that records can be copied verbatim.
+struct Recorder<'a, R> {
+    input: &'a mut R,
+    raw: &'a mut Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.raw.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// The bytes which records are read from, such as a stream or a byte slice.
+pub(crate) trait ByteSource: Read {
+    /// The number of bytes which were read, from an arbitrary start.
+    fn position(&self) -> usize;
+}
+
+impl<R: Read> ByteSource for Recorder<'_, R> {
+    fn position(&self) -> usize {
+        self.raw.len()
+    }
+}
+
+impl ByteSource for io::Cursor<&[u8]> {
+    fn position(&self) -> usize {
+        io::Cursor::position(self) as usize
+    }
+}
+
+/// Reads the records of a [`ByteSource`], keeping track of the state for relative records.
+/// [`Decoder`] and [`SliceDecoder`](crate::mapped::SliceDecoder) share it.
+pub(crate) struct RecordReader<const N: usize> {
+    state: Game<N, 4>,
+    metadata: MetadataCoder,
+    result: Option<GameResult>,
+    /// The position of the channels of the last record in its source.
+    pub(crate) payload_start: usize,
+    /// The position of the metadata of the last record in its source.
+    pub(crate) metadata_start: usize,
+}
+
+impl<const N: usize> RecordReader<N>
+where
+    Reserves<N>: Default,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Game::default(),
+            metadata: MetadataCoder::default(),
+            result: None,
+            payload_start: 0,
+            metadata_start: 0,
+        }
+    }
+
+    /// Read the next record, which must be there.
+    pub(crate) fn read(
+        &mut self,
+        input: &mut impl ByteSource,
+        header: &Header,
+    ) -> Result<Record<N>, DecodeError> {
+        let stored = read_stored(input)?;
+        let mut action = None;
+        match stored {
+            Stored::Action(relative) => {
+                self.state
+                    .play(relative)
+                    .map_err(|err| DecodeError::IllegalAction(relative, err))?;
+                action = Some(relative);
+            }
+            Stored::Diff(changed) => {
+                let mut bits = BitIterator::new();
+                self.state = decode_diff(&self.state, changed, || bits.next(input))?;
+            }
+            Stored::Full => {
+                self.state = read_state(input)?;
+                self.metadata.reset();
+                if header.result {
+                    self.result = read_result(input)?;
+                }
+            }
+        }
+        self.payload_start = input.position();
+        let mut record = read_payload(input, header, action, self.state.clone())?;
+        record.diff = matches!(stored, Stored::Diff(_));
+        self.metadata_start = input.position();
+        record.metadata = self.metadata.read(input, &header.columns)?;
+        record.result = self.result;
+        Ok(record)
+    }
+}
+
+/// Writes records, storing states relative to the previous one when possible.
+///
+/// The header is not written by the encoder, so write it with [`Header::write`]
+/// at the start of every file.
+pub struct Encoder<const N: usize> {
+    header: Header,
+    metadata: MetadataCoder,
+    /// The result of the current run of relative records.
+    result: Option<GameResult>,
+    previous: Option<Game<N, 4>>,
+    action_buffer: Vec<Move>,
+}
+
+impl<const N: usize> Default for Encoder<N> {
+    fn default() -> Self {
+        Self::with_header(Header::default())
+    }
+}
+
+impl<const N: usize> Encoder<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_header(header: Header) -> Self {
+        Self {
+            header,
+            metadata: MetadataCoder::default(),
+            result: None,
+            previous: None,
+            action_buffer: vec![],
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Forget the previous state so that the next state is written in full.
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.metadata.reset();
+    }
+
+    /// Check if this state is reachable with one action from the previous one.
+    pub fn find_action(&mut self, state: &Game<N, 4>) -> Option<Move> {
+        let previous = self.previous.as_ref()?;
+        if self.action_buffer.is_empty() {
+            previous.possible_moves(&mut self.action_buffer);
+        }
+        self.action_buffer.iter().copied().find(|&action| {
+            let mut next = previous.clone();
+            next.play(action).expect(
+                "The previously generated actions should be valid to play on the previous state.",
+            );
+            next.reversible_plies = state.reversible_plies; // This is not stored in the TPS
+            next.board == state.board && next.to_move == state.to_move
+        })
+    }
+
+    /// Write the state (relative / diff / full) and remember it for the next record.
+    /// With a result channel, a full state is followed by the result of the last record
+    /// written with [`Encoder::write_record`].
+    pub fn write_relative_state(
+        &mut self,
+        output: &mut impl Write,
+        state: &Game<N, 4>,
+    ) -> io::Result<usize> {
+        match self.find_action(state) {
+            Some(action) => {
+                let written = write_action(output, Some(action))?;
+                self.remember(state);
+                Ok(written)
+            }
+            None => self.write_unreachable_state(output, state),
+        }
+    }
+
+    /// Write a state which no action reaches from the previous one,
+    /// as a diff if that is smaller than the full state.
+    fn write_unreachable_state(
+        &mut self,
+        output: &mut impl Write,
+        state: &Game<N, 4>,
+    ) -> io::Result<usize> {
+        let Some(diff) = self
+            .previous
+            .as_ref()
+            .and_then(|previous| diff(previous, state))
+        else {
+            return self.write_full_state(output, state);
+        };
+        let mut full = vec![];
+        write_state(&mut full, state)?;
+        // A tie goes to the full state, which starts a new block.
+        let full_size = 1 + full.len() + usize::from(self.header.result);
+        if diff.len() >= full_size {
+            return self.write_full_state(output, state);
+        }
+        output.write_all(&diff)?;
+        self.remember(state);
+        Ok(diff.len())
+    }
+
+    fn write_full_state(
+        &mut self,
+        output: &mut impl Write,
+        state: &Game<N, 4>,
+    ) -> io::Result<usize> {
+        let mut written = write_action(output, None)?;
+        written += write_state(output, state)?;
+        if self.header.result {
+            written += write_result(output, self.result)?;
+        }
+        self.metadata.reset();
+        self.remember(state);
+        Ok(written)
+    }
+
+    fn remember(&mut self, state: &Game<N, 4>) {
+        self.previous = Some(state.clone());
+        self.action_buffer.clear();
+    }
+
+    /// Write the state and the channels of the header.
+    /// The action of the record is ignored, since it is found again.
+    /// With a result channel, a record whose result differs from the previous one
+    /// starts a new run with its full state.
+    pub fn write_record(
+        &mut self,
+        output: &mut impl Write,
+        record: &Record<N>,
+    ) -> io::Result<usize> {
+        let mut written = if self.header.result && record.result != self.result {
+            self.result = record.result;
+            self.write_full_state(output, &record.state)?
+        } else {
+            self.write_relative_state(output, &record.state)?
+        };
+        written += write_payload(output, &self.header, record)?;
+        written += self
+            .metadata
+            .write(output, &self.header.columns, &record.metadata)?;
+        Ok(written)
+    }
+
+    /// Copy a decoded record verbatim, unless it is relative and its action
+    /// does not lead from the previous state written by this encoder
+    /// (or its metadata was encoded after a different record, or its result differs),
+    /// in which case the state is written as a diff or in full.
+    /// Diffs are always written again, since they are relative to the state before them.
+    /// The decoder must have the same header as the encoder.
+    pub fn copy_record<R>(
+        &mut self,
+        output: &mut impl Write,
+        record: &Record<N>,
+        decoder: &Decoder<N, R>,
+    ) -> io::Result<usize> {
+        let follows_previous = |action| {
+            self.previous.as_ref().is_some_and(|previous| {
+                let mut next = previous.clone();
+                next.play(action).is_ok()
+                    && next.board == record.state.board
+                    && next.to_move == record.state.to_move
+            })
+        };
+        let same_result = self.result == decoder.reader.result;
+        let verbatim = match record.action {
+            Some(action) => {
+                follows_previous(action)
+                    && self.metadata == decoder.previous_metadata
+                    && same_result
+            }
+            None => !record.diff,
+        };
+        self.result = decoder.reader.result;
+        if verbatim {
+            output.write_all(decoder.raw())?;
+            self.metadata.clone_from(&decoder.reader.metadata);
+            self.remember(&record.state);
+            return Ok(decoder.raw().len());
+        }
+        let mut written = if same_result {
+            self.write_unreachable_state(output, &record.state)?
+        } else {
+            self.write_full_state(output, &record.state)?
+        };
+        let channels = &decoder.raw()[decoder.reader.payload_start..decoder.reader.metadata_start];
+        output.write_all(channels)?;
+        written += self
+            .metadata
+            .write(output, &self.header.columns, &record.metadata)?;
+        Ok(written + channels.len())
+    }
+}
+
+pub fn write_action(output: &mut impl Write, action: Option<Move>) -> io::Result<usize> {
+    let Some(action) = action else {
+        // zero-byte means state is not relative.
+        output.write_all(&[0x00])?;
+        return Ok(1);
+    };
+
+    let first = if let MoveKind::Spread(_, pattern) = action.kind() {
+        let mask = pattern.mask();
+        assert_ne!(mask, 0x00, "picking up 0 is impossible");
+        assert_ne!(mask, 0xff, "moving 8 times is impossible");
+        mask
+    } else {
+        0xFF // indicate the action is a placement
+    };
+
+    let second = {
+        let square = action.square();
+        let col = square.column();
+        let row = square.row();
+        assert!(row < 8);
+        assert!(col < 8);
+        let square_bits = (row << 3) | col;
+
+        let last_two = match action.kind() {
+            MoveKind::Place(Piece::Flat) => 0b01,
+            MoveKind::Place(Piece::Wall) => 0b10,
+            MoveKind::Place(Piece::Cap) => 0b11,
+            MoveKind::Spread(Direction::Up, _) => 0b00,
+            MoveKind::Spread(Direction::Down, _) => 0b01,
+            MoveKind::Spread(Direction::Left, _) => 0b10,
+            MoveKind::Spread(Direction::Right, _) => 0b11,
+        };
+
+        (last_two << 6) | square_bits
+    };
+
+    output.write_all(&[first, second])?;
+    Ok(2)
+}
+
+pub fn write_state<const N: usize, const HALF_KOMI: i8>(
+    output: &mut impl Write,
+    state: &Game<N, HALF_KOMI>,
+) -> io::Result<usize> {
+    let mut bitvec = BitVec::<u8, Lsb0>::new();
+    bitvec.push(state.to_move == Color::White); // to_move
+    for stack in state.board.iter().flatten() {
+        push_stack(&mut bitvec, stack);
+    }
+    let vec: Vec<u8> = bitvec.into_vec();
+    output.write_all(&vec)?;
+    Ok(vec.len())
+}
+
+fn push_stack(bitvec: &mut BitVec<u8, Lsb0>, stack: &Stack) {
+    let Some((piece, top_color)) = stack.top() else {
+        bitvec.push(false); // unoccupied
+        return;
+    };
+    bitvec.push(true); // occupied
+    match piece {
+        Piece::Flat => bitvec.push(false), // nonblocking (i.e. flat)
+        Piece::Cap => {
+            bitvec.push(true); // blocking
+            bitvec.push(true); // & road (i.e. cap)
+        }
+        Piece::Wall => {
+            bitvec.push(true); // blocking
+            bitvec.push(false); // & not road (i.e. wall)
+        }
+    }
+    if stack.size() > 1 {
+        bitvec.push(true); // stack is large
+        assert!(stack.size() < 128);
+        let size_bitvec = BitVec::<u8, Lsb0>::from_element(stack.size() as u8);
+        bitvec.extend(size_bitvec.into_iter().take(7)); // size of stack
+        bitvec.extend(stack.colors().into_iter().map(|c| c == Color::White));
+    } else {
+        bitvec.push(false); // stack is small
+        bitvec.push(top_color == Color::White); // just the color
+    }
+}
+
+/// The bytes of a diff from the previous state, or `None` if too many squares changed.
+fn diff<const N: usize>(previous: &Game<N, 4>, state: &Game<N, 4>) -> Option<Vec<u8>> {
+    let changed: Vec<_> = (0..N * N)
+        .map(|i| (i, Square::new((i % N) as u8, (i / N) as u8)))
+        .filter(|&(_, square)| previous.board.get(square) != state.board.get(square))
+        .collect();
+    let count = u8::try_from(changed.len())
+        .ok()
+        .filter(|&count| count < 64)?;
+
+    let mut bitvec = BitVec::<u8, Lsb0>::new();
+    bitvec.push(state.to_move == Color::White); // to_move
+    for (i, square) in changed {
+        let index_bitvec = BitVec::<u8, Lsb0>::from_element(i as u8);
+        bitvec.extend(index_bitvec.into_iter().take(6)); // index of the square
+        push_stack(&mut bitvec, state.board.get(square).unwrap());
+    }
+    let mut bytes = vec![DIFF, count];
+    bytes.extend(bitvec.into_vec());
+    Some(bytes)
+}
+
+/// Write the final result of a game as a single byte, where zero is an unknown result.
+pub fn write_result(output: &mut impl Write, result: Option<GameResult>) -> io::Result<usize> {
+    let reason = |reason| match reason {
+        WinReason::Road => 0,
+        WinReason::Flat => 1,
+        WinReason::Other => 2,
+    };
+    let byte = match result {
+        None => 0,
+        Some(GameResult::Draw) => 1,
+        Some(GameResult::White(win)) => 2 + reason(win),
+        Some(GameResult::Black(win)) => 5 + reason(win),
+    };
+    output.write_all(&[byte])?;
+    Ok(1)
+}
+
+/// Write the channels of the header except for the metadata,
+/// failing if the record does not have one of them.
+pub fn write_payload<const N: usize>(
+    output: &mut impl Write,
+    header: &Header,
+    record: &Record<N>,
+) -> io::Result<usize> {
+    let missing = |channel| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the record has no {channel}"),
+        )
+    };
+    let mut written = 0;
+    if header.value {
+        written += write_value(output, record.value.ok_or_else(|| missing("value"))?)?;
+    }
+    if header.ube {
+        written += write_f32(output, record.ube.ok_or_else(|| missing("UBE"))?)?;
+    }
+    if header.visits {
+        let visits = record
+            .visits
+            .as_ref()
+            .ok_or_else(|| missing("visit counts"))?;
+        written += visits.write(output)?;
+    } else if header.policy {
+        let policy = record.policy.as_ref().ok_or_else(|| missing("policy"))?;
+        written += write_policy(output, policy)?;
+    }
+    Ok(written)
+}
+
+fn write_f32(output: &mut impl Write, x: f32) -> io::Result<usize> {
+    let bytes = x.to_le_bytes();
+    output.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+/// Write the value in 16 bits, failing if it is not in [-1, 1].
+pub fn write_value(output: &mut impl Write, value: f32) -> io::Result<usize> {
+    if !(-1.0..=1.0).contains(&value) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the value {value} is not in [-1, 1]"),
+        ));
+    }
+    let compressed: u16 = (((f64::from(value) + 1.0) / 2.0) * f64::from(0xFFFF)).round() as u16;
+    let bytes = compressed.to_le_bytes();
+    output.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+pub fn write_policy(output: &mut impl Write, policy: &[(Move, f32)]) -> io::Result<usize> {
+    assert!((MIN_PROBABILITY.ln() - LOG_MIN).abs() < 1e-6);
+
+    let mut written = 0;
+    for &(action, probability) in policy {
+        let Some(compressed) = quantize_probability(probability)? else {
+            continue; // skip low probability actions
+        };
+        let bytes = compressed.to_le_bytes();
+        written += write_action(output, Some(action))?;
+        output.write_all(&bytes)?;
+        written += bytes.len();
+    }
+    // empty action to mark end of policy
+    written += write_action(output, None)?;
+
+    Ok(written)
+}
+
+/// The log-probability scaled to 16 bits, or `None` if the probability
+/// is below [`MIN_PROBABILITY`] and is not stored.
+/// Fails if the probability is NaN or above one.
+pub fn quantize_probability(probability: f32) -> io::Result<Option<u16>> {
+    if probability.is_nan() || probability > 1.0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the probability {probability} is not in [0, 1]"),
+        ));
+    }
+    let probability = f64::from(probability);
+    if probability < MIN_PROBABILITY {
+        return Ok(None);
+    }
+    let log_prob = probability.ln();
+    Ok(Some(
+        ((log_prob / LOG_MIN) * f64::from(0xFFFF)).round() as u16
+    ))
+}
+
+pub(crate) fn read_byte(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub fn read_result(input: &mut impl Read) -> Result<Option<GameResult>, DecodeError> {
+    const REASONS: [WinReason; 3] = [WinReason::Road, WinReason::Flat, WinReason::Other];
+    let byte = read_byte(input)?;
+    Ok(match byte {
+        0 => None,
+        1 => Some(GameResult::Draw),
+        2..=4 => Some(GameResult::White(REASONS[usize::from(byte - 2)])),
+        5..=7 => Some(GameResult::Black(REASONS[usize::from(byte - 5)])),
+        _ => return Err(DecodeError::InvalidResult(byte)),
+    })
+}
+
+/// The first byte of a diff, which is followed by the number of changed squares.
+/// Placements always set one of the top two bits of their second byte, so this is not an action.
+const DIFF: u8 = 0xFF;
+
+/// How the state of a record is stored.
+enum Stored {
+    Full,
+    Action(Move),
+    /// A diff with this many changed squares.
+    Diff(u8),
+}
+
+fn read_stored(input: &mut impl Read) -> Result<Stored, DecodeError> {
+    let first = read_byte(input)?;
+    if first == 0x00 {
+        return Ok(Stored::Full);
+    }
+    let second = read_byte(input)?;
+    if first == DIFF && second >> 6 == 0 {
+        Ok(Stored::Diff(second))
+    } else {
+        decode_action(first, second).map(Stored::Action)
+    }
+}
+
+pub fn read_action(input: &mut impl Read) -> Result<Option<Move>, DecodeError> {
+    let pattern = read_byte(input)?;
+    if pattern == 0x00 {
+        return Ok(None);
+    }
+    let second = read_byte(input)?;
+    decode_action(pattern, second).map(Some)
+}
+
+/// Decode the two bytes of a non-zero action.
+fn decode_action(pattern: u8, second: u8) -> Result<Move, DecodeError> {
+    let col = second & 0b111;
+    let row = (second >> 3) & 0b111;
+    let square = Square::new(col, row);
+    let last_two_bits = second >> 6;
+    if pattern == 0xFF {
+        let piece = match last_two_bits {
+            0b01 => Piece::Flat,
+            0b10 => Piece::Wall,
+            0b11 => Piece::Cap,
+            _ => return Err(DecodeError::InvalidAction(pattern, second)),
+        };
+        Ok(Move::new(square, MoveKind::Place(piece)))
+    } else {
+        let direction = match last_two_bits {
+            0b00 => Direction::Up,
+            0b01 => Direction::Down,
+            0b10 => Direction::Left,
+            0b11 => Direction::Right,
+            _ => unreachable!(),
+        };
+        Ok(Move::new(
+            square,
+            MoveKind::Spread(direction, Pattern::from_mask(pattern)),
+        ))
+    }
+}
+
+pub fn read_state<const N: usize, const HALF_KOMI: i8>(
+    input: &mut impl Read,
+) -> Result<Game<N, HALF_KOMI>, DecodeError>
+where
+    Reserves<N>: Default,
+{
+    let mut bits = BitIterator::new();
+    decode_state(|| bits.next(input))
+}
+
+/// Decode a full state from its bits.
+fn decode_state<const N: usize, const HALF_KOMI: i8>(
+    mut next_bit: impl FnMut() -> io::Result<bool>,
+) -> Result<Game<N, HALF_KOMI>, DecodeError>
+where
+    Reserves<N>: Default,
+{
+    let to_move = if next_bit()? {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let mut board = Board::default();
+    for i in 0..(N * N) {
+        let row = (i / N) as u8;
+        let col = (i % N) as u8;
+        let board_stack = board.get_mut(Square::new(col, row)).unwrap();
+        *board_stack = decode_stack(&mut next_bit)?;
+    }
+
+    game_from_board(board, to_move)
+}
+
+/// Decode a diff with `changed` squares from its bits, and apply it to the previous state.
+fn decode_diff<const N: usize, const HALF_KOMI: i8>(
+    previous: &Game<N, HALF_KOMI>,
+    changed: u8,
+    mut next_bit: impl FnMut() -> io::Result<bool>,
+) -> Result<Game<N, HALF_KOMI>, DecodeError>
+where
+    Reserves<N>: Default,
+{
+    let to_move = if next_bit()? {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let mut board = previous.board.clone();
+    for _ in 0..changed {
+        let mut index = 0;
+        for _ in 0..6 {
+            index |= u8::from(next_bit()?) << 6;
+            index >>= 1;
+        }
+        if usize::from(index) >= N * N {
+            return Err(DecodeError::InvalidSquare(index));
+        }
+        let row = index / N as u8;
+        let col = index % N as u8;
+        let board_stack = board.get_mut(Square::new(col, row)).unwrap();
+        *board_stack = decode_stack(&mut next_bit)?;
+    }
+
+    game_from_board(board, to_move)
+}
+
+/// The game of a decoded board, failing if a player has more stones or capstones
+/// on the board than the reserves of the size, which a corrupt record may claim.
+fn game_from_board<const N: usize, const HALF_KOMI: i8>(
+    board: Board<N>,
+    to_move: Color,
+) -> Result<Game<N, HALF_KOMI>, DecodeError>
+where
+    Reserves<N>: Default,
+{
+    let reserves = Reserves::<N>::default();
+    for color in [Color::White, Color::Black] {
+        let mut pieces = 0;
+        let mut caps = 0;
+        // Iterating over the colors of an empty stack overflows.
+        for stack in board.iter().flatten().filter(|stack| stack.top().is_some()) {
+            pieces += stack.colors().into_iter().filter(|&c| c == color).count();
+            if stack.top() == Some((Piece::Cap, color)) {
+                caps += 1;
+            }
+        }
+        if caps > usize::from(reserves.caps) || pieces - caps > usize::from(reserves.stones) {
+            return Err(DecodeError::TooManyPieces(color));
+        }
+    }
+    Ok(Game::from_board_and_to_move(board, to_move, None))
+}
+
+/// Decode the stack of one square, which is empty if the square is unoccupied.
+fn decode_stack(next_bit: &mut impl FnMut() -> io::Result<bool>) -> Result<Stack, DecodeError> {
+    let occupied = next_bit()?;
+    if !occupied {
+        return Ok(Stack::default());
+    }
+    let blocking = next_bit()?;
+    let road = if blocking { next_bit()? } else { true };
+    let piece = match (blocking, road) {
+        (false, true) => Piece::Flat,
+        (true, false) => Piece::Wall,
+        (true, true) => Piece::Cap,
+        _ => unreachable!(),
+    };
+    let big_stack = next_bit()?;
+    if big_stack {
+        let mut size = 0;
+        for _ in 0..7 {
+            size |= u8::from(next_bit()?) << 7;
+            size >>= 1;
+        }
+        if !(2..64).contains(&size) {
+            return Err(DecodeError::InvalidStackSize(size));
+        }
+        let mut colors = Colors::default();
+        for _ in 0..size {
+            colors.push(if next_bit()? {
+                Color::White
+            } else {
+                Color::Black
+            });
+        }
+        Ok(Stack::exact(piece, colors))
+    } else {
+        let white = next_bit()?;
+        let colors = Colors::of_one(if white { Color::White } else { Color::Black });
+        Ok(Stack::exact(piece, colors))
+    }
+}
+
+/// Reads the bits of bytes from an input in the order of a `BitVec<u8, Lsb0>`,
+/// reading a byte only when its first bit is needed.
+pub(crate) struct BitIterator {
+    byte: u8,
+    read: u8,
+}
+
+impl BitIterator {
+    pub(crate) fn new() -> Self {
+        Self {
+            byte: 0,
+            read: u8::MAX,
+        }
+    }
+
+    pub(crate) fn next(&mut self, input: &mut impl Read) -> io::Result<bool> {
+        if self.read >= 8 {
+            self.byte = read_byte(input)?;
+            self.read = 0;
+        }
+        let out = (self.byte >> self.read) & 1 != 0;
+        self.read += 1;
+        Ok(out)
+    }
+}
+
+/// Read the channels of the header after the state, and assemble the record.
+/// The metadata follows, and is read with a [`MetadataCoder`].
+pub fn read_payload<const N: usize>(
+    input: &mut impl Read,
+    header: &Header,
+    action: Option<Move>,
+    state: Game<N, 4>,
+) -> Result<Record<N>, DecodeError> {
+    let value = header.value.then(|| read_value(input)).transpose()?;
+    let ube = header.ube.then(|| read_f32(input)).transpose()?;
+    let visits = header.visits.then(|| Visits::read(input)).transpose()?;
+    let policy = match &visits {
+        Some(visits) => Some(visits.policy().into_vec()),
+        None => header.policy.then(|| read_policy(input)).transpose()?,
+    };
+    Ok(Record {
+        action,
+        diff: false,
+        state,
+        value,
+        ube,
+        policy,
+        visits,
+        metadata: vec![],
+        result: None,
+    })
+}
+
+fn read_f32(input: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+pub fn read_value(input: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 2];
+    input.read_exact(&mut bytes)?;
+    let compressed = u16::from_le_bytes(bytes);
+    Ok((f64::from(compressed) / f64::from(0xFFFF) * 2.0 - 1.0) as f32)
+}
+
+pub fn read_policy(input: &mut impl Read) -> Result<Vec<(Move, f32)>, DecodeError> {
+    let mut policy = vec![];
+    while let Some(action) = read_action(input)? {
+        let mut bytes = [0; 2];
+        input.read_exact(&mut bytes)?;
+        let compressed = u16::from_le_bytes(bytes);
+        let logit = f64::from(compressed) * LOG_MIN / f64::from(0xFFFF);
+        let probability = logit.exp();
+        policy.push((action, probability as f32))
+    }
+
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{index::Index, mapped::SliceDecoder};
+
+    #[test]
+    fn test_round_trip() {
+        let game = Game::<6, 4>::from_ptn_moves(&["a1", "f6", "b2", "Cc3", "b2<", "c3<"]);
+        let mut next = game.clone();
+        next.play("a2>".parse().unwrap()).unwrap();
+        let policy = [("a1".parse().unwrap(), 0.75), ("b1".parse().unwrap(), 0.25)];
+
+        let mut output = vec![];
+        let mut encoder = Encoder::<6>::new();
+        encoder
+            .write_record(
+                &mut output,
+                &Record::new(game.clone(), 0.5, policy.to_vec()),
+            )
+            .unwrap();
+        encoder
+            .write_record(
+                &mut output,
+                &Record::new(next.clone(), -0.5, policy.to_vec()),
+            )
+            .unwrap();
+
+        let records: Vec<_> = Decoder::<6, _>::new(output.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, None);
+        assert_eq!(records[1].action, Some("a2>".parse().unwrap()));
+        for (record, state) in records.iter().zip([&game, &next]) {
+            assert_eq!(record.state.board, state.board);
+            assert_eq!(record.state.to_move, state.to_move);
+            assert_eq!(record.policy.as_ref().unwrap().len(), policy.len());
+        }
+        assert!((records[1].value.unwrap() + 0.5).abs() < 1e-4);
+
+        for value in [1.5, f32::NAN] {
+            let record = Record::new(next.clone(), value, policy.to_vec());
+            assert!(encoder.write_record(&mut vec![], &record).is_err());
+        }
+        for probability in [1.5, f32::NAN] {
+            assert!(quantize_probability(probability).is_err());
+        }
+        assert_eq!(quantize_probability(1.0).unwrap(), Some(0));
+        assert_eq!(quantize_probability(0.0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_header_channels() {
+        let game = Game::<5, 4>::from_ptn_moves(&["a1", "e5"]);
+        let header = Header {
+            value: true,
+            ube: true,
+            policy: false,
+            visits: false,
+            result: false,
+            columns: vec!["game:int".parse().unwrap()],
+        };
+        let record = Record {
+            ube: Some(2.5),
+            policy: None,
+            metadata: vec![("game".to_string(), Metadata::Int(12))],
+            ..Record::new(game, -0.25, vec![])
+        };
+
+        let mut output = vec![];
+        header.write(&mut output).unwrap();
+        let mut encoder = Encoder::<5>::with_header(header.clone());
+        encoder.write_record(&mut output, &record).unwrap();
+        let without_ube = Record {
+            ube: None,
+            ..record
+        };
+        assert!(encoder.write_record(&mut vec![], &without_ube).is_err());
+
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        assert_eq!(decoder.header().unwrap(), &header);
+        let decoded = decoder.read_record().unwrap().unwrap();
+        assert!((decoded.value.unwrap() + 0.25).abs() < 1e-4);
+        assert_eq!(decoded.ube, Some(2.5));
+        assert_eq!(decoded.metadata, [("game".to_string(), Metadata::Int(12))]);
+        assert!(decoded.policy.is_none());
+        assert!(decoder.read_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_visit_counts() {
+        let game = Game::<5, 4>::from_ptn_moves(&["a1", "e5"]);
+        let header = Header {
+            visits: true,
+            ..Header::default()
+        };
+        let visits: Visits = "1001,c3:700,Sc3:0,b2:300".parse().unwrap();
+        let record = Record {
+            visits: Some(visits.clone()),
+            ..Record::new(game, 0.5, visits.policy().into_vec())
+        };
+
+        let mut output = vec![];
+        header.write(&mut output).unwrap();
+        Encoder::<5>::with_header(header)
+            .write_record(&mut output, &record)
+            .unwrap();
+        let decoded = Decoder::<5, _>::new(output.as_slice())
+            .next()
+            .unwrap()
+            .unwrap();
+        let decoded_visits = decoded.visits.as_ref().unwrap();
+        assert_eq!(decoded_visits.total, 1001);
+        assert_eq!(
+            decoded_visits.counts[..],
+            [visits.counts[0], visits.counts[2]]
+        );
+        assert_eq!(decoded.policy.as_ref().unwrap()[0].1, 0.7);
+        let completed = decoded.completed_policy().unwrap();
+        assert_eq!(completed.iter().filter(|(_, p)| *p > 0.0).count(), 2);
+
+        let unvisited: Visits = "3,c3:0".parse().unwrap();
+        let record = Record {
+            visits: Some(unvisited.clone()),
+            ..Record::new(decoded.state, 0.5, unvisited.policy().into_vec())
+        };
+        let completed = record.completed_policy().unwrap();
+        assert!(completed.iter().all(|&(_, p)| p == 0.0));
+    }
+
+    #[test]
+    fn test_result_runs() {
+        let header = Header {
+            policy: false,
+            result: true,
+            ..Header::default()
+        };
+        let road = Some(GameResult::White(WinReason::Road));
+        let mut output = vec![];
+        header.write(&mut output).unwrap();
+        let mut encoder = Encoder::<5>::with_header(header);
+        // The third position continues the game, but it is from a game with another result.
+        for (ply, result) in [(1, road), (2, road), (3, Some(GameResult::Draw)), (4, None)] {
+            let game = Game::<5, 4>::from_ptn_moves(&["a1", "e5", "c3", "d4"][..ply]);
+            let record = Record {
+                policy: None,
+                result,
+                ..Record::new(game, 0.0, vec![])
+            };
+            encoder.write_record(&mut output, &record).unwrap();
+        }
+
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        let mut runs = vec![];
+        while let Some(record) = decoder.read_record().unwrap() {
+            runs.push((record.action.is_some(), record.result));
+        }
+        assert_eq!(
+            runs,
+            [
+                (false, road),
+                (true, road),
+                (false, Some(GameResult::Draw)),
+                (false, None)
+            ]
+        );
+        let decoded: Vec<_> = SliceDecoder::<5>::new(&output)
+            .unwrap()
+            .map(|record| record.unwrap().result)
+            .collect();
+        assert_eq!(
+            decoded,
+            runs.iter().map(|&(_, result)| result).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_copy_record() {
+        let moves = ["a1", "e5", "c3", "d4", "b2", "b3"];
+        let mut output = vec![];
+        let mut encoder = Encoder::<5>::new();
+        for ply in 0..=moves.len() {
+            let game = Game::<5, 4>::from_ptn_moves(&moves[..ply]);
+            let record = Record::new(game, 0.0, vec![]);
+            encoder.write_record(&mut output, &record).unwrap();
+        }
+        let records: Vec<_> = Decoder::<5, _>::new(output.as_slice())
+            .map(Result::unwrap)
+            .collect();
+
+        // After the same previous states every record is copied verbatim.
+        let mut copied = vec![];
+        let mut encoder = Encoder::<5>::new();
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        while let Some(record) = decoder.read_record().unwrap() {
+            encoder.copy_record(&mut copied, &record, &decoder).unwrap();
+        }
+        assert_eq!(copied, output);
+
+        // Without a previous state a relative record is written in full.
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        let record = decoder.nth(1).unwrap().unwrap();
+        assert!(record.action.is_some());
+        let mut copied = vec![];
+        Encoder::<5>::new()
+            .copy_record(&mut copied, &record, &decoder)
+            .unwrap();
+        assert_eq!(copied[0], 0x00);
+        assert!(Decoder::<5, _>::new(copied.as_slice())
+            .next()
+            .unwrap()
+            .unwrap()
+            .is_keyframe());
+
+        // After an unrelated previous state it is written as a diff,
+        // and the record after it follows again.
+        let mut copied = vec![];
+        let mut encoder = Encoder::<5>::new();
+        encoder.write_record(&mut copied, &records[6]).unwrap();
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        for i in 0..=5 {
+            let record = decoder.read_record().unwrap().unwrap();
+            if i >= 4 {
+                encoder.copy_record(&mut copied, &record, &decoder).unwrap();
+            }
+        }
+        let decoded: Vec<_> = Decoder::<5, _>::new(copied.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        let kinds: Vec<_> = decoded
+            .iter()
+            .map(|r| (r.is_keyframe(), r.diff, r.action.is_some()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (true, false, false),
+                (false, true, false),
+                (false, false, true)
+            ]
+        );
+        for (record, expected) in decoded.iter().zip([&records[6], &records[4], &records[5]]) {
+            assert_eq!(record.state.board, expected.state.board);
+            assert_eq!(record.state.to_move, expected.state.to_move);
+        }
+    }
+
+    #[test]
+    fn test_diffs() {
+        let start = Game::<5, 4>::from_ptn_moves(&["a1", "e5", "c3", "d4"]);
+        let later = Game::<5, 4>::from_ptn_moves(&["a1", "e5", "c3", "d4", "b2", "b3"]);
+        let mut output = vec![];
+        let mut encoder = Encoder::<5>::new();
+        for game in [&start, &later, &start] {
+            let record = Record::new(game.clone(), 0.0, vec![]);
+            encoder.write_record(&mut output, &record).unwrap();
+        }
+
+        let records: Vec<_> = Decoder::<5, _>::new(output.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        let sliced: Vec<_> = SliceDecoder::<5>::new(&output)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for (record, game) in records
+            .iter()
+            .chain(&sliced)
+            .zip([&start, &later, &start].repeat(2))
+        {
+            assert_eq!(record.state.board, game.board);
+            assert_eq!(record.state.to_move, game.to_move);
+            assert_eq!(record.state.white_reserves, game.white_reserves);
+        }
+        let kinds: Vec<_> = records.iter().map(|r| (r.is_keyframe(), r.diff)).collect();
+        assert_eq!(kinds, [(true, false), (false, true), (false, true)]);
+        let index = Index::build::<5>(output.as_slice()).unwrap();
+        assert_eq!(index.blocks(), 1);
+
+        // A diff is written again relative to the same previous state, so it does not change.
+        let mut copied = vec![];
+        let mut encoder = Encoder::<5>::new();
+        let mut decoder = Decoder::<5, _>::new(output.as_slice());
+        while let Some(record) = decoder.read_record().unwrap() {
+            encoder.copy_record(&mut copied, &record, &decoder).unwrap();
+        }
+        assert_eq!(copied, output);
+    }
+
+    #[test]
+    fn test_too_many_pieces() {
+        let decode = |bits: Vec<bool>| {
+            let mut bits = bits.into_iter();
+            decode_state::<3, 0>(|| Ok(bits.next().unwrap_or(false)))
+        };
+        // White to move, with a white capstone on the first square, but size 3 has none.
+        let cap = vec![true, true, true, true, false, true];
+        assert!(matches!(
+            decode(cap),
+            Err(DecodeError::TooManyPieces(Color::White))
+        ));
+        // A stack of eleven black flats, but size 3 has ten stones.
+        let mut stones = vec![true, true, false, true];
+        stones.extend((0..7).map(|i| 11 >> i & 1 == 1));
+        stones.extend([false; 11]);
+        assert!(matches!(
+            decode(stones),
+            Err(DecodeError::TooManyPieces(Color::Black))
+        ));
+    }
+}
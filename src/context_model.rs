@@ -0,0 +1,309 @@
+//! Context modeling of the quantized policy log-probabilities.
+//!
+//! The probability of a move depends strongly on its kind: placements of flats,
+//! walls, and capstones, and spreads in each direction, which take one or more stones.
+//! Every such [`MoveClass`] has its own adaptive model of the 16-bit symbols which
+//! [`write_policy`](crate::codec::write_policy) stores. The high [`MODELED_BITS`]
+//! are coded with a binary tree of adaptive probabilities, and the low bits,
+//! which are close to uniform, are coded directly. The bits go through a range coder
+//! (the binary coder of LZMA), so a symbol costs close to its negative log-likelihood under the model.
+//!
+//! The actions themselves are not coded here, since the decoder needs them to pick the context.
+
+use std::io::{self, Write};
+
+use takparse::{Direction, Move, MoveKind, Piece};
+
+/// Number of high bits of a symbol which are modeled.
+pub const MODELED_BITS: u32 = 8;
+const DIRECT_BITS: u32 = 16 - MODELED_BITS;
+
+const PROBABILITY_BITS: u32 = 11;
+const ONE: u16 = 1 << PROBABILITY_BITS;
+/// How fast the probabilities adapt, as a shift of the error.
+const ADAPTATION: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+/// The kind of a move which selects its model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveClass {
+    Place(Piece),
+    /// A direction, and whether more than one stone is taken.
+    Spread(Direction, bool),
+}
+
+impl MoveClass {
+    const COUNT: usize = 11;
+
+    pub fn of(action: Move) -> Self {
+        match action.kind() {
+            MoveKind::Place(piece) => Self::Place(piece),
+            MoveKind::Spread(direction, pattern) => {
+                Self::Spread(direction, pattern.count_pieces() > 1)
+            }
+        }
+    }
+
+    fn index(self) -> usize {
+        let direction = |direction| match direction {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        };
+        match self {
+            Self::Place(Piece::Flat) => 0,
+            Self::Place(Piece::Wall) => 1,
+            Self::Place(Piece::Cap) => 2,
+            Self::Spread(d, false) => 3 + direction(d),
+            Self::Spread(d, true) => 7 + direction(d),
+        }
+    }
+}
+
+/// Adaptive probabilities of the binary tree of every move class.
+#[derive(Clone)]
+struct Model {
+    trees: Vec<[u16; 1 << MODELED_BITS]>,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self {
+            trees: vec![[ONE / 2; 1 << MODELED_BITS]; MoveClass::COUNT],
+        }
+    }
+}
+
+fn update(probability: &mut u16, bit: bool) {
+    if bit {
+        *probability -= *probability >> ADAPTATION;
+    } else {
+        *probability += (ONE - *probability) >> ADAPTATION;
+    }
+}
+
+/// Codes the symbols of a sequence of policies into an output.
+pub struct PolicyEncoder<W> {
+    model: Model,
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    output: W,
+    written: usize,
+}
+
+impl<W: Write> PolicyEncoder<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            model: Model::default(),
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            output,
+            written: 0,
+        }
+    }
+
+    /// Code the quantized log-probability of an action.
+    pub fn encode(&mut self, action: Move, symbol: u16) -> io::Result<()> {
+        let class = MoveClass::of(action).index();
+        let mut node = 1;
+        for i in (DIRECT_BITS..16).rev() {
+            let bit = (symbol >> i) & 1 == 1;
+            let mut probability = self.model.trees[class][node];
+            self.encode_bit(&mut probability, bit)?;
+            self.model.trees[class][node] = probability;
+            node = (node << 1) | usize::from(bit);
+        }
+        for i in (0..DIRECT_BITS).rev() {
+            self.encode_direct((symbol >> i) & 1 == 1)?;
+        }
+        Ok(())
+    }
+
+    /// The number of bytes written so far, which includes all of them after [`Self::finish`].
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Write the pending bytes. No more symbols may be encoded afterwards.
+    pub fn finish(&mut self) -> io::Result<()> {
+        for _ in 0..5 {
+            self.shift_low()?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    fn encode_bit(&mut self, probability: &mut u16, bit: bool) -> io::Result<()> {
+        let bound = (self.range >> PROBABILITY_BITS) * u32::from(*probability);
+        if bit {
+            self.low += u64::from(bound);
+            self.range -= bound;
+        } else {
+            self.range = bound;
+        }
+        update(probability, bit);
+        self.normalize()
+    }
+
+    fn encode_direct(&mut self, bit: bool) -> io::Result<()> {
+        self.range >>= 1;
+        if bit {
+            self.low += u64::from(self.range);
+        }
+        self.normalize()
+    }
+
+    fn normalize(&mut self) -> io::Result<()> {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low()?;
+        }
+        Ok(())
+    }
+
+    fn shift_low(&mut self) -> io::Result<()> {
+        if self.low < 0xFF00_0000 || self.low >= 1 << 32 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.cache_size > 0 {
+                self.output.write_all(&[byte.wrapping_add(carry)])?;
+                self.written += 1;
+                byte = 0xFF;
+                self.cache_size -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+        Ok(())
+    }
+}
+
+/// Decodes the symbols written by a [`PolicyEncoder`], given the same actions.
+pub struct PolicyDecoder<'a> {
+    model: Model,
+    input: &'a [u8],
+    code: u32,
+    range: u32,
+}
+
+impl<'a> PolicyDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            model: Model::default(),
+            input,
+            code: 0,
+            range: u32::MAX,
+        };
+        // The first byte is always zero.
+        decoder.next_byte();
+        for _ in 0..4 {
+            decoder.code = (decoder.code << 8) | u32::from(decoder.next_byte());
+        }
+        decoder
+    }
+
+    /// Decode the quantized log-probability of an action.
+    pub fn decode(&mut self, action: Move) -> u16 {
+        let class = MoveClass::of(action).index();
+        let mut node = 1;
+        for _ in DIRECT_BITS..16 {
+            let mut probability = self.model.trees[class][node];
+            let bit = self.decode_bit(&mut probability);
+            self.model.trees[class][node] = probability;
+            node = (node << 1) | usize::from(bit);
+        }
+        let mut symbol = (node - (1 << MODELED_BITS)) as u16;
+        for _ in 0..DIRECT_BITS {
+            symbol = (symbol << 1) | u16::from(self.decode_direct());
+        }
+        symbol
+    }
+
+    /// Input past the end reads as zeros, so a truncated input decodes garbage instead of failing.
+    fn next_byte(&mut self) -> u8 {
+        let Some((&byte, rest)) = self.input.split_first() else {
+            return 0;
+        };
+        self.input = rest;
+        byte
+    }
+
+    fn decode_bit(&mut self, probability: &mut u16) -> bool {
+        let bound = (self.range >> PROBABILITY_BITS) * u32::from(*probability);
+        let bit = self.code >= bound;
+        if bit {
+            self.code -= bound;
+            self.range -= bound;
+        } else {
+            self.range = bound;
+        }
+        update(probability, bit);
+        self.normalize();
+        bit
+    }
+
+    fn decode_direct(&mut self) -> bool {
+        self.range >>= 1;
+        let bit = self.code >= self.range;
+        if bit {
+            self.code -= self.range;
+        }
+        self.normalize();
+        bit
+    }
+
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(self.next_byte());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_context_round_trip() {
+        let actions: Vec<Move> = ["a1", "Sb2", "Cc3", "a1>", "3b2<12", "c3+", "2d4-"]
+            .into_iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let symbols: Vec<(Move, u16)> = (0..10_000)
+            .map(|i| {
+                let action = actions[i % actions.len()];
+                // Placements are likely, and spreads of many stones are not.
+                let mean = 10_000.0 * (i % actions.len()) as f64;
+                let symbol = (mean + rng.gen_range(0.0..2_000.0)).min(65_535.0) as u16;
+                (action, symbol)
+            })
+            .collect();
+
+        let mut encoder = PolicyEncoder::new(vec![]);
+        for &(action, symbol) in &symbols {
+            encoder.encode(action, symbol).unwrap();
+        }
+        encoder.finish().unwrap();
+        assert_eq!(encoder.written(), encoder.output.len());
+        let bytes = encoder.into_inner();
+        assert!(bytes.len() < 2 * symbols.len());
+
+        let mut decoder = PolicyDecoder::new(&bytes);
+        for &(action, symbol) in &symbols {
+            assert_eq!(decoder.decode(action), symbol);
+        }
+    }
+}
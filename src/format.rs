@@ -0,0 +1,239 @@
+//! Output formats for decompressed targets.
+//!
+//! Implement [`OutputFormat`] to add a new format,
+//! and pass it to [`write_records`] together with a decoder.
+
+use std::io::{self, Write};
+
+use takparse::{Move, Tps};
+
+use crate::{
+    codec::{DecodeError, Record},
+    tensor::{move_index, state_planes, NpzWriter, TensorWriter},
+    Target,
+};
+
+pub trait OutputFormat<const N: usize> {
+    /// Write one target. The policy contains all possible moves,
+    /// and is `None` if the record has no policy.
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()>;
+
+    /// Called after the last target.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Write all decoded records in the given format.
+/// Returns the number of records written.
+pub fn write_records<const N: usize>(
+    records: impl Iterator<Item = Result<Record<N>, DecodeError>>,
+    mut format: Box<dyn OutputFormat<N> + '_>,
+) -> Result<usize, DecodeError> {
+    let mut written = 0;
+    for record in records {
+        let record = record?;
+        format.write(&record, record.completed_policy().as_deref())?;
+        written += 1;
+    }
+    format.finish()?;
+    Ok(written)
+}
+
+fn write_policy(output: &mut impl Write, policy: &[(Move, f32)]) -> io::Result<()> {
+    for (i, (action, probability)) in policy.iter().enumerate() {
+        if i > 0 {
+            output.write_all(b" ")?;
+        }
+        write!(output, "{action}:{probability}")?;
+    }
+    Ok(())
+}
+
+/// `{tps};{value};{policy}` lines, or `{tps};{value};{ube};{policy}`
+/// lines which can be parsed into a [`Target`].
+/// A missing UBE is written as NaN, and other missing targets as empty fields.
+/// Metadata is written as a fifth field, and the game result as a sixth.
+/// Policies with visit counts are written as the counts.
+pub struct Text<W> {
+    pub output: W,
+    pub ube: bool,
+}
+
+impl<const N: usize, W: Write> OutputFormat<N> for Text<W> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        let target = Target {
+            tps: record.state.clone().into(),
+            value: record.value,
+            ube: self.ube.then(|| record.ube.unwrap_or(f32::NAN)),
+            policy: policy.map(Into::into),
+            visits: record.visits.clone(),
+            metadata: record.metadata.clone(),
+            result: record.result,
+        };
+        writeln!(self.output, "{target}")
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// One JSON object per line, as written by [`Target::to_json`].
+pub struct JsonLines<W> {
+    pub output: W,
+}
+
+impl<const N: usize, W: Write> OutputFormat<N> for JsonLines<W> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        let target = Target {
+            tps: record.state.clone().into(),
+            value: record.value,
+            ube: record.ube,
+            policy: policy.map(Into::into),
+            visits: record.visits.clone(),
+            metadata: record.metadata.clone(),
+            result: record.result,
+        };
+        writeln!(self.output, "{}", target.to_json())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Comma-separated values with a header line.
+/// The policy is a single quoted column of space-separated `move:probability` pairs.
+/// Missing targets are empty columns.
+pub struct Csv<W> {
+    pub output: W,
+    header_written: bool,
+}
+
+impl<W> Csv<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            header_written: false,
+        }
+    }
+}
+
+impl<const N: usize, W: Write> OutputFormat<N> for Csv<W> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        if !self.header_written {
+            self.output.write_all(b"tps,value,ube,policy\n")?;
+            self.header_written = true;
+        }
+        let tps = Tps::from(record.state.clone());
+        write!(self.output, "\"{tps}\",")?;
+        if let Some(value) = record.value {
+            write!(self.output, "{value}")?;
+        }
+        self.output.write_all(b",")?;
+        if let Some(ube) = record.ube {
+            write!(self.output, "{ube}")?;
+        }
+        self.output.write_all(b",")?;
+        if let Some(policy) = policy {
+            self.output.write_all(b"\"")?;
+            write_policy(&mut self.output, policy)?;
+            self.output.write_all(b"\"")?;
+        }
+        self.output.write_all(b"\n")
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Fixed-layout little-endian records:
+/// the input planes (see [`crate::tensor`]) as `u8`, the value and UBE as `f32`,
+/// the number of policy entries as `u32`, and then each entry as
+/// a `u32` [`move_index`] and an `f32` probability.
+/// A missing value or UBE is NaN, and a missing policy has no entries.
+pub struct Binary<W> {
+    pub output: W,
+    planes: Vec<u8>,
+}
+
+impl<W> Binary<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            planes: vec![],
+        }
+    }
+}
+
+impl<const N: usize, W: Write> OutputFormat<N> for Binary<W> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        self.planes.clear();
+        state_planes(&record.state, &mut self.planes);
+        self.output.write_all(&self.planes)?;
+        let value = record.value.unwrap_or(f32::NAN);
+        self.output.write_all(&value.to_le_bytes())?;
+        let ube = record.ube.unwrap_or(f32::NAN);
+        self.output.write_all(&ube.to_le_bytes())?;
+        let policy = policy.unwrap_or_default();
+        self.output
+            .write_all(&(policy.len() as u32).to_le_bytes())?;
+        for &(action, probability) in policy {
+            self.output
+                .write_all(&(move_index::<N>(action) as u32).to_le_bytes())?;
+            self.output.write_all(&probability.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<const N: usize> OutputFormat<N> for TensorWriter<N> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        let value = record.value.unwrap_or(f32::NAN);
+        TensorWriter::write(self, &record.state, value, policy.unwrap_or_default())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        TensorWriter::finish(*self)
+    }
+}
+
+impl<const N: usize> OutputFormat<N> for NpzWriter<N> {
+    fn write(&mut self, record: &Record<N>, policy: Option<&[(Move, f32)]>) -> io::Result<()> {
+        let value = record.value.unwrap_or(f32::NAN);
+        let policy = policy.unwrap_or_default();
+        NpzWriter::write(self, &record.state, value, record.ube, policy)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        NpzWriter::finish(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fast_tak::Game;
+
+    use super::*;
+
+    #[test]
+    fn test_text_with_ube_parses() {
+        let record = Record::new(Game::<5, 4>::from_ptn_moves(&["a1", "e5"]), 0.25, vec![]);
+        let policy = record.completed_policy().unwrap();
+        let mut format = Text {
+            output: vec![],
+            ube: true,
+        };
+        OutputFormat::<5>::write(&mut format, &record, Some(&policy)).unwrap();
+
+        let line = String::from_utf8(format.output).unwrap();
+        let target: Target = line.parse().unwrap();
+        assert_eq!(target.value, Some(0.25));
+        assert!(target.ube.unwrap().is_nan());
+        assert_eq!(target.policy.unwrap().len(), policy.len());
+    }
+}
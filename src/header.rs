@@ -0,0 +1,181 @@
+//! Header which declares the channels stored in every record.
+//!
+//! Files which store a value and a policy for every record need no header,
+//! so they start with their first record, whose first byte is zero.
+//! Other files start with [`MAGIC`], a version byte, a byte of channel flags,
+//! and the number of metadata columns followed by each column's type byte and its name,
+//! prefixed by its length.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    codec::DecodeError,
+    metadata::{Column, ColumnType},
+};
+
+pub const MAGIC: &[u8; 4] = b"TAKT";
+const VERSION: u8 = 1;
+
+const VALUE: u8 = 1 << 0;
+const UBE: u8 = 1 << 1;
+const POLICY: u8 = 1 << 2;
+const RESULT: u8 = 1 << 3;
+const VISITS: u8 = 1 << 4;
+
+/// The channels which follow the state of every record, in this order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The value, quantized to 16 bits.
+    pub value: bool,
+    /// The UBE as an `f32`.
+    pub ube: bool,
+    /// The policy as quantized log-probabilities.
+    pub policy: bool,
+    /// The policy as visit counts instead, see [`crate::visits`].
+    /// Only valid together with `policy`.
+    pub visits: bool,
+    /// The final result of the game, which is stored once per run of relative records,
+    /// right after the full state which starts it.
+    pub result: bool,
+    /// Metadata columns, see [`crate::metadata`].
+    pub columns: Vec<Column>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            value: true,
+            ube: false,
+            policy: true,
+            visits: false,
+            result: false,
+            columns: vec![],
+        }
+    }
+}
+
+impl Header {
+    /// Write the header, or nothing if it has the default channels.
+    pub fn write(&self, output: &mut impl Write) -> io::Result<usize> {
+        if *self == Self::default() {
+            return Ok(0);
+        }
+        if self.visits && !self.policy {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "visit counts without a policy",
+            ));
+        }
+        let flags = [
+            (self.value, VALUE),
+            (self.ube, UBE),
+            (self.policy, POLICY),
+            (self.visits, VISITS),
+            (self.result, RESULT),
+        ]
+        .into_iter()
+        .filter(|&(present, _)| present)
+        .fold(0, |flags, (_, flag)| flags | flag);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([VERSION, flags]);
+        let count = u8::try_from(self.columns.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many columns"))?;
+        bytes.push(count);
+        for column in &self.columns {
+            let len = u8::try_from(column.name.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "column name too long"))?;
+            bytes.extend([column.kind.to_byte(), len]);
+            bytes.extend_from_slice(column.name.as_bytes());
+        }
+        output.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Read a header from an input which starts with [`MAGIC`].
+    pub fn read(input: &mut impl Read) -> Result<Self, DecodeError> {
+        let mut read_byte = || {
+            let mut byte = [0];
+            input.read_exact(&mut byte)?;
+            Ok::<_, io::Error>(byte[0])
+        };
+        let mut magic = [0; 4];
+        for byte in &mut magic {
+            *byte = read_byte()?;
+        }
+        if &magic != MAGIC {
+            return Err(DecodeError::InvalidHeader("wrong magic bytes"));
+        }
+        if read_byte()? != VERSION {
+            return Err(DecodeError::InvalidHeader("unsupported version"));
+        }
+        let flags = read_byte()?;
+        if flags & !(VALUE | UBE | POLICY | RESULT | VISITS) != 0 {
+            return Err(DecodeError::InvalidHeader("unknown channel"));
+        }
+        if flags & VISITS != 0 && flags & POLICY == 0 {
+            return Err(DecodeError::InvalidHeader("visit counts without a policy"));
+        }
+        let columns = (0..read_byte()?)
+            .map(|_| {
+                let kind = ColumnType::from_byte(read_byte()?)
+                    .ok_or(DecodeError::InvalidHeader("unknown column type"))?;
+                let name = (0..read_byte()?)
+                    .map(|_| read_byte())
+                    .collect::<io::Result<Vec<u8>>>()?;
+                let name = String::from_utf8(name)
+                    .map_err(|_| DecodeError::InvalidHeader("column name is not UTF-8"))?;
+                Ok(Column { name, kind })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        Ok(Self {
+            value: flags & VALUE != 0,
+            ube: flags & UBE != 0,
+            policy: flags & POLICY != 0,
+            visits: flags & VISITS != 0,
+            result: flags & RESULT != 0,
+            columns,
+        })
+    }
+
+    /// Read the header at the start of the bytes, if there is one.
+    /// Returns the header and its length.
+    pub fn detect(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        if bytes.first() != Some(&MAGIC[0]) {
+            return Ok((Self::default(), 0));
+        }
+        let mut rest = bytes;
+        let header = Self::read(&mut rest)?;
+        Ok((header, bytes.len() - rest.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut bytes = vec![];
+        assert_eq!(Header::default().write(&mut bytes).unwrap(), 0);
+        assert_eq!(
+            Header::detect(&[0x00, 0x01]).unwrap(),
+            (Header::default(), 0)
+        );
+
+        let header = Header {
+            value: true,
+            ube: true,
+            policy: false,
+            visits: false,
+            result: true,
+            columns: vec!["game:int".parse().unwrap(), "network:str".parse().unwrap()],
+        };
+        let written = header.write(&mut bytes).unwrap();
+        bytes.push(0x00);
+        assert_eq!(Header::detect(&bytes).unwrap(), (header, written));
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([VERSION, VISITS, 0]);
+        assert!(Header::detect(&bytes).is_err());
+    }
+}
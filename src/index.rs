@@ -0,0 +1,136 @@
+//! Index of the records which store their state in full.
+//!
+//! Decoding can only start at such a keyframe, so the records between two
+//! keyframes form a block which is decoded as a whole.
+
+use std::io::{self, BufRead, Read, Write};
+
+use fast_tak::Reserves;
+
+use crate::{
+    codec::{DecodeError, Decoder},
+    header::Header,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keyframe {
+    /// Byte offset of the record in the compressed file.
+    pub offset: u64,
+    /// Index of the record in the compressed file.
+    pub record: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Index {
+    header: Header,
+    keyframes: Vec<Keyframe>,
+    records: u64,
+    bytes: u64,
+}
+
+impl Index {
+    /// Build the index in one pass over a compressed file.
+    pub fn build<const N: usize>(input: impl BufRead) -> Result<Self, DecodeError>
+    where
+        Reserves<N>: Default,
+    {
+        let mut decoder = Decoder::<N, _>::new(input);
+        let mut keyframes = vec![];
+        let mut records = 0;
+        while let Some(record) = decoder.read_record()? {
+            if record.is_keyframe() {
+                keyframes.push(Keyframe {
+                    offset: decoder.offset(),
+                    record: records,
+                });
+            }
+            records += 1;
+        }
+        let bytes = decoder.offset() + decoder.raw().len() as u64;
+        Ok(Self {
+            header: decoder.header()?.clone(),
+            keyframes,
+            records,
+            bytes,
+        })
+    }
+
+    /// The header of the compressed file, which the blocks are decoded with.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Number of records in the compressed file.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Number of blocks, i.e. keyframes.
+    pub fn blocks(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// The block which contains the record.
+    pub fn block_of(&self, record: u64) -> usize {
+        assert!(record < self.records, "record {record} is out of bounds");
+        self.keyframes.partition_point(|k| k.record <= record) - 1
+    }
+
+    /// The first keyframe of the block.
+    pub fn keyframe(&self, block: usize) -> Keyframe {
+        self.keyframes[block]
+    }
+
+    /// Number of records and bytes in the block.
+    pub fn block_len(&self, block: usize) -> (u64, u64) {
+        let start = self.keyframes[block];
+        let (end_record, end_offset) = match self.keyframes.get(block + 1) {
+            Some(end) => (end.record, end.offset),
+            None => (self.records, self.bytes),
+        };
+        (end_record - start.record, end_offset - start.offset)
+    }
+
+    /// Save the index so that it does not have to be rebuilt.
+    /// The header of the file follows the keyframes, unless it has the default channels.
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        output.write_all(&self.records.to_le_bytes())?;
+        output.write_all(&self.bytes.to_le_bytes())?;
+        output.write_all(&(self.keyframes.len() as u64).to_le_bytes())?;
+        for keyframe in &self.keyframes {
+            output.write_all(&keyframe.offset.to_le_bytes())?;
+            output.write_all(&keyframe.record.to_le_bytes())?;
+        }
+        self.header.write(output)?;
+        Ok(())
+    }
+
+    /// Load an index saved with [`Index::write`], which must be the rest of the input.
+    pub fn read(input: &mut impl Read) -> io::Result<Self> {
+        let mut read_u64 = || {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            Ok::<_, io::Error>(u64::from_le_bytes(bytes))
+        };
+        let records = read_u64()?;
+        let bytes = read_u64()?;
+        let keyframes = (0..read_u64()?)
+            .map(|_| {
+                Ok(Keyframe {
+                    offset: read_u64()?,
+                    record: read_u64()?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let mut rest = vec![];
+        input.read_to_end(&mut rest)?;
+        let (header, _) = Header::detect(&rest)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        Ok(Self {
+            header,
+            keyframes,
+            records,
+            bytes,
+        })
+    }
+}
@@ -0,0 +1,520 @@
+//! Targets as JSON objects, one per line:
+//! `{"tps":"x6/x6/x6/x6/x6/x6 1 1","value":0.5,"ube":null,"policy":{"a1":0.2,...}}`.
+//!
+//! The value, UBE, and policy may be `null` or missing, where an empty policy is also
+//! a missing one, like in the text format. Metadata is an optional
+//! `"metadata":{"game":12,"network":"gen-42"}` object, where numbers with a decimal point
+//! or an exponent are floats and `null` is a missing cell. The game result is an optional
+//! `"result":"R-0"` field in PTN notation. Visit counts are an optional
+//! `"visits":{"total":800,"counts":{"a1":500,...}}` object, from which the policy
+//! is normalized if the object has none. Unknown fields are ignored.
+//!
+//! Every JSON writer of the crate and of the `targets` binary writes its strings
+//! with [`write_string`] and its floats with [`write_number`], so that they are escaped the same way.
+
+use std::fmt::{self, Write};
+
+use crate::{metadata::Metadata, visits::Visits, ParseTargetError, Target};
+
+impl Target {
+    /// Parse a target from a JSON object.
+    pub fn from_json(s: &str) -> Result<Self, ParseTargetError> {
+        let mut parser = Parser {
+            bytes: s.trim().as_bytes(),
+            position: 0,
+        };
+        let target = parser.target()?;
+        if parser.position < parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(target)
+    }
+
+    /// Write the target as a JSON object on a single line.
+    /// Non-finite numbers are written as `null`.
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        self.write_json(&mut s)
+            .expect("Writing to a string should not fail");
+        s
+    }
+
+    fn write_json(&self, s: &mut String) -> fmt::Result {
+        write!(s, r#"{{"tps":"{}","value":"#, self.tps)?;
+        write_number(s, self.value.unwrap_or(f32::NAN))?;
+        s.push_str(r#","ube":"#);
+        write_number(s, self.ube.unwrap_or(f32::NAN))?;
+        if !self.metadata.is_empty() {
+            s.push_str(r#","metadata":{"#);
+            for (i, (name, cell)) in self.metadata.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                write_string(s, name, &[])?;
+                s.push(':');
+                match cell {
+                    Metadata::Int(int) => write!(s, "{int}")?,
+                    Metadata::Float(float) if float.is_finite() => write!(s, "{float:?}")?,
+                    Metadata::Float(_) => s.push_str("null"),
+                    Metadata::Str(string) => write_string(s, string, &[])?,
+                }
+            }
+            s.push('}');
+        }
+        if let Some(result) = self.result {
+            write!(s, r#","result":"{result}""#)?;
+        }
+        if let Some(visits) = &self.visits {
+            write!(s, r#","visits":{{"total":{},"counts":{{"#, visits.total)?;
+            for (i, (action, count)) in visits.counts.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                write!(s, r#""{action}":{count}"#)?;
+            }
+            s.push_str("}}");
+        }
+        s.push_str(r#","policy":"#);
+        let Some(policy) = self.policy.as_ref().filter(|policy| !policy.is_empty()) else {
+            s.push_str("null}");
+            return Ok(());
+        };
+        s.push('{');
+        for (i, (action, probability)) in policy.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(s, r#""{action}":"#)?;
+            write_number(s, *probability)?;
+        }
+        s.push_str("}}");
+        Ok(())
+    }
+}
+
+/// Write a JSON string literal, where the characters in `also` are escaped as `\uXXXX`
+/// like the control characters, such as the separators of the text format.
+pub fn write_string(s: &mut impl Write, string: &str, also: &[char]) -> fmt::Result {
+    s.write_char('"')?;
+    for c in string.chars() {
+        match c {
+            '"' => s.write_str("\\\"")?,
+            '\\' => s.write_str("\\\\")?,
+            '\n' => s.write_str("\\n")?,
+            '\t' => s.write_str("\\t")?,
+            '\r' => s.write_str("\\r")?,
+            c if c < ' ' || also.contains(&c) => write!(s, "\\u{:04x}", u32::from(c))?,
+            c => s.write_char(c)?,
+        }
+    }
+    s.write_char('"')
+}
+
+/// The JSON string literal of `string`, for writers which format a whole object at once.
+pub fn quote(string: &str) -> String {
+    let mut s = String::new();
+    write_string(&mut s, string, &[]).expect("Writing to a string should not fail");
+    s
+}
+
+/// Parse a JSON string literal which makes up all of `s`.
+pub(crate) fn parse_string(s: &str) -> Result<String, ParseTargetError> {
+    let mut parser = Parser {
+        bytes: s.as_bytes(),
+        position: 0,
+    };
+    let string = parser.string()?;
+    if parser.position < parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(string)
+}
+
+/// Write a float as a JSON number, or `null` if it is not finite, since JSON has no NaN.
+pub fn write_number(s: &mut impl Write, x: f32) -> fmt::Result {
+    if x.is_finite() {
+        write!(s, "{x}")
+    } else {
+        s.write_str("null")
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> ParseTargetError {
+        ParseTargetError::Json(self.position, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<(), ParseTargetError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(message));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Call `field` for every key of an object. It must consume the value.
+    fn object(
+        &mut self,
+        mut field: impl FnMut(&mut Self, String) -> Result<(), ParseTargetError>,
+    ) -> Result<(), ParseTargetError> {
+        self.expect(b'{', "expected an object")?;
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':', "expected a colon")?;
+            field(self, key)?;
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected a comma or the end of the object")),
+            }
+        }
+    }
+
+    /// Call `element` for every element of an array. It must consume the element.
+    fn array(
+        &mut self,
+        mut element: impl FnMut(&mut Self) -> Result<(), ParseTargetError>,
+    ) -> Result<(), ParseTargetError> {
+        self.expect(b'[', "expected an array")?;
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            element(self)?;
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected a comma or the end of the array")),
+            }
+        }
+    }
+
+    fn target(&mut self) -> Result<Target, ParseTargetError> {
+        let mut tps = None;
+        let mut value = None;
+        let mut ube = None;
+        let mut metadata = vec![];
+        let mut policy = None;
+        let mut result = None;
+        let mut visits = None;
+        self.object(|parser, key| {
+            match key.as_str() {
+                "tps" => tps = Some(parser.string()?.parse()?),
+                "value" => value = parser.number()?,
+                "ube" => ube = parser.number()?,
+                "metadata" => parser.object(|parser, name| {
+                    if let Some(cell) = parser.cell()? {
+                        metadata.push((name, cell));
+                    }
+                    Ok(())
+                })?,
+                "result" if parser.peek() == Some(b'n') => parser.literal("null")?,
+                "result" => result = Some(parser.string()?.parse()?),
+                "visits" if parser.peek() == Some(b'n') => parser.literal("null")?,
+                "visits" => visits = Some(parser.visits()?),
+                "policy" if parser.peek() == Some(b'n') => parser.literal("null")?,
+                "policy" => {
+                    let mut entries = vec![];
+                    parser.object(|parser, action| {
+                        let probability = parser
+                            .number()?
+                            .ok_or(ParseTargetError::WrongPolicyFormat)?;
+                        entries.push((action.parse()?, probability));
+                        Ok(())
+                    })?;
+                    policy = (!entries.is_empty()).then(|| entries.into_boxed_slice());
+                }
+                _ => parser.skip_value()?,
+            }
+            Ok(())
+        })?;
+        if policy.is_none() {
+            policy = visits.as_ref().map(Visits::policy);
+        }
+        Ok(Target {
+            tps: tps.ok_or(ParseTargetError::MissingTps)?,
+            value,
+            ube,
+            policy,
+            visits,
+            metadata,
+            result,
+        })
+    }
+
+    fn string(&mut self) -> Result<String, ParseTargetError> {
+        self.expect(b'"', "expected a string")?;
+        let mut s = String::new();
+        loop {
+            let start = self.position;
+            while !matches!(self.bytes.get(self.position), None | Some(b'"' | b'\\')) {
+                self.position += 1;
+            }
+            s.push_str(
+                std::str::from_utf8(&self.bytes[start..self.position])
+                    .expect("The input was a string"),
+            );
+            match self.bytes.get(self.position) {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    let escaped = match self.bytes.get(self.position + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => {
+                            self.position += 2;
+                            s.push(self.unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("unsupported escape sequence")),
+                    };
+                    s.push(escaped);
+                    self.position += 2;
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// The character of a `\uXXXX` escape after the `\u`,
+    /// which is followed by a second escape if it is the high half of a surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, ParseTargetError> {
+        let high = self.hex_digits()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.bytes.get(self.position..self.position + 2) != Some(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.hex_digits()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, ParseTargetError> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        let digits = std::str::from_utf8(digits).expect("The digits are ASCII");
+        let code = u32::from_str_radix(digits, 16).expect("The digits were checked");
+        self.position += 4;
+        Ok(code)
+    }
+
+    /// A number, or `None` for `null`.
+    fn number(&mut self) -> Result<Option<f32>, ParseTargetError> {
+        if self.peek() == Some(b'n') {
+            return self.literal("null").map(|()| None);
+        }
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(self.error("expected a number"));
+        }
+        let number = std::str::from_utf8(&self.bytes[start..self.position])
+            .expect("The number is ASCII")
+            .parse()?;
+        Ok(Some(number))
+    }
+
+    fn visits(&mut self) -> Result<Visits, ParseTargetError> {
+        let mut total = None;
+        let mut counts = vec![];
+        self.object(|parser, key| {
+            match key.as_str() {
+                "total" => total = Some(parser.count()?),
+                "counts" => parser.object(|parser, action| {
+                    counts.push((action.parse()?, parser.count()?));
+                    Ok(())
+                })?,
+                _ => parser.skip_value()?,
+            }
+            Ok(())
+        })?;
+        Ok(Visits {
+            total: total.ok_or_else(|| self.error("visits without a total"))?,
+            counts: counts.into_boxed_slice(),
+        })
+    }
+
+    /// A non-negative integer.
+    fn count(&mut self) -> Result<u32, ParseTargetError> {
+        self.skip_whitespace();
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_digit)
+        {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(self.error("expected a count"));
+        }
+        Ok(std::str::from_utf8(&self.bytes[start..self.position])
+            .expect("The count is ASCII")
+            .parse()?)
+    }
+
+    /// A metadata cell, or `None` for `null`.
+    fn cell(&mut self) -> Result<Option<Metadata>, ParseTargetError> {
+        match self.peek() {
+            Some(b'"') => Ok(Some(Metadata::Str(self.string()?))),
+            Some(b'n') => self.literal("null").map(|()| None),
+            _ => {
+                let start = self.position;
+                let Some(float) = self.number()? else {
+                    return Ok(None);
+                };
+                let number = &self.bytes[start..self.position];
+                if number.iter().any(|b| matches!(b, b'.' | b'e' | b'E')) {
+                    return Ok(Some(Metadata::Float(float)));
+                }
+                let int = std::str::from_utf8(number)
+                    .expect("The number is ASCII")
+                    .parse()
+                    .map_err(|_| self.error("integer out of range"))?;
+                Ok(Some(Metadata::Int(int)))
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &'static str) -> Result<(), ParseTargetError> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            return Err(self.error("unexpected literal"));
+        }
+        self.position += literal.len();
+        Ok(())
+    }
+
+    fn skip_value(&mut self) -> Result<(), ParseTargetError> {
+        match self.peek() {
+            Some(b'"') => self.string().map(|_| ()),
+            Some(b'{') => self.object(|parser, _| parser.skip_value()),
+            Some(b'[') => self.array(Self::skip_value),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            _ => self.number().map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let line = r#"{"tps":"x5/x5/x2,1,x2/x5/2,x4 1 2","value":-0.25,"ube":null,"policy":{"a1":0.75,"Sb2":0.25}}"#;
+        let target = Target::from_json(line).unwrap();
+        assert_eq!(target.value, Some(-0.25));
+        assert_eq!(target.ube, None);
+        assert_eq!(target.policy.as_ref().unwrap().len(), 2);
+        assert_eq!(target.to_json(), line);
+
+        let reordered = r#" { "policy": {}, "extra": [1, {"a": true}], "ube": 1.5e0, "value": 1, "tps": "x3/x3/x3 1 1" } "#;
+        let target = Target::from_json(reordered).unwrap();
+        assert_eq!(target.ube, Some(1.5));
+        assert!(target.policy.is_none());
+
+        let value_only = r#"{"tps":"x3/x3/x3 1 1","value":0.5,"ube":null,"metadata":{"game":12,"temperature":1.0,"network":"gen \"42\""},"result":"0-F","policy":null}"#;
+        let target = Target::from_json(value_only).unwrap();
+        assert!(target.policy.is_none());
+        assert_eq!(target.metadata[0].1, Metadata::Int(12));
+        assert_eq!(target.metadata[1].1, Metadata::Float(1.0));
+        assert_eq!(
+            target.metadata[2].1,
+            Metadata::Str("gen \"42\"".to_string())
+        );
+        assert_eq!(target.result, Some("0-F".parse().unwrap()));
+        assert_eq!(target.to_json(), value_only);
+
+        // As written by Python's `json.dumps`, which escapes everything but ASCII.
+        let escaped = r#"{"tps":"x3/x3/x3 1 1","value":0.5,"ube":null,"metadata":{"network":"gen-\u00e9\ud83d\ude00\u0007"}}"#;
+        let target = Target::from_json(escaped).unwrap();
+        assert_eq!(
+            target.metadata[0].1,
+            Metadata::Str("gen-\u{e9}\u{1f600}\u{7}".to_string())
+        );
+        let reparsed = Target::from_json(&target.to_json()).unwrap();
+        assert_eq!(reparsed.metadata, target.metadata);
+        for unpaired in [r#"\ud83d"#, r#"\ud83d\u0041"#, r#"\ude00"#, r#"\u00g1"#] {
+            let line = escaped.replace(r#"\ud83d\ude00"#, unpaired);
+            assert!(matches!(
+                Target::from_json(&line),
+                Err(ParseTargetError::Json(..))
+            ));
+        }
+
+        let visits = r#"{"tps":"x3/x3/x3 1 1","value":0.5,"ube":null,"visits":{"total":5,"counts":{"a1":3,"b2":1}},"policy":{"a1":0.75,"b2":0.25}}"#;
+        let target = Target::from_json(visits).unwrap();
+        assert_eq!(target.visits.as_ref().unwrap().total, 5);
+        assert_eq!(target.to_json(), visits);
+        let without_policy = visits.replace(r#","policy":{"a1":0.75,"b2":0.25}"#, "");
+        let target = Target::from_json(&without_policy).unwrap();
+        assert_eq!(target.policy.unwrap()[0].1, 0.75);
+
+        let target = Target::from_json(r#"{"tps":"x3/x3/x3 1 1","policy":{}}"#).unwrap();
+        assert_eq!(target.value, None);
+        assert!(matches!(
+            Target::from_json(r#"{"value":0,"policy":{}}"#),
+            Err(ParseTargetError::MissingTps)
+        ));
+        assert!(matches!(
+            Target::from_json(r#"{"tps":"x3/x3/x3 1 1","value":0,"policy":{"a1":0.5,}}"#),
+            Err(ParseTargetError::Json(..))
+        ));
+    }
+}
@@ -1,10 +1,36 @@
-use std::{num::ParseFloatError, str::FromStr};
+use std::{
+    fmt,
+    num::{ParseFloatError, ParseIntError},
+    str::FromStr,
+};
 
-use takparse::{Move, ParseMoveError, ParseTpsError, Tps};
+use fast_tak::{Game, Reserves};
+use takparse::{Color, GameResult, Move, ParseGameResultError, ParseMoveError, ParseTpsError, Tps};
 use thiserror::Error;
 
+use crate::{
+    metadata::{ColumnType, Metadata},
+    visits::Visits,
+};
+
+pub mod codec;
+pub mod context_model;
+pub mod format;
+pub mod header;
+pub mod index;
+pub mod json;
+pub mod mapped;
+pub mod metadata;
+pub mod npy;
+pub mod ptn;
+pub mod sampler;
+pub mod tensor;
+pub mod visits;
+
 pub const MIN_PROBABILITY: f64 = 1e-5;
 pub const LOG_MIN: f64 = -11.512925464970229; // MIN_PROBABILITY.ln();
+/// How far the sum of a policy may be from one before it counts as unnormalized.
+pub const NORMALIZATION_TOLERANCE: f32 = 1e-3;
 
 #[derive(Error, Debug)]
 pub enum ParseTargetError {
@@ -14,25 +40,104 @@ pub enum ParseTargetError {
     MissingValue,
     #[error("missing policy")]
     MissingPolicy,
+    #[error("missing UBE")]
+    MissingUbe,
+    #[error("missing visit counts")]
+    MissingVisits,
+    #[error("the header has no column {0}")]
+    UnknownColumn(String),
+    #[error("column {0} has the wrong type")]
+    WrongColumnType(String),
     #[error("policy format is wrong")]
     WrongPolicyFormat,
+    #[error("metadata format is wrong")]
+    WrongMetadataFormat,
     #[error("{0}")]
     Tps(#[from] ParseTpsError),
     #[error("{0}")]
     Action(#[from] ParseMoveError),
     #[error("{0}")]
     Float(#[from] ParseFloatError),
+    #[error("{0}")]
+    Int(#[from] ParseIntError),
+    #[error("{0}")]
+    GameResult(#[from] ParseGameResultError),
     #[error("policy is NaN")]
     PolicyNan,
+    #[error("policy is negative")]
+    PolicyNegative,
+    #[error("policy is above one")]
+    PolicyAboveOne,
+    #[error("policy sums to {0}")]
+    PolicyUnnormalized(f32),
+    #[error("value {0} is not in [-1, 1]")]
+    ValueOutOfRange(f32),
+    #[error("UBE {0} is not finite")]
+    UbeNotFinite(f32),
+    #[error("move {0} is not legal")]
+    IllegalMove(Move),
+    #[error("the TPS is for size {0}")]
+    WrongSize(usize),
+    #[error("invalid JSON at byte {0}: {1}")]
+    Json(usize, &'static str),
+}
+
+impl ParseTargetError {
+    /// The name of the variant, for counting errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MissingTps => "MissingTps",
+            Self::MissingValue => "MissingValue",
+            Self::MissingPolicy => "MissingPolicy",
+            Self::MissingUbe => "MissingUbe",
+            Self::MissingVisits => "MissingVisits",
+            Self::UnknownColumn(_) => "UnknownColumn",
+            Self::WrongColumnType(_) => "WrongColumnType",
+            Self::WrongPolicyFormat => "WrongPolicyFormat",
+            Self::WrongMetadataFormat => "WrongMetadataFormat",
+            Self::Tps(_) => "Tps",
+            Self::Action(_) => "Action",
+            Self::Float(_) => "Float",
+            Self::Int(_) => "Int",
+            Self::GameResult(_) => "GameResult",
+            Self::PolicyNan => "PolicyNan",
+            Self::PolicyNegative => "PolicyNegative",
+            Self::PolicyAboveOne => "PolicyAboveOne",
+            Self::PolicyUnnormalized(_) => "PolicyUnnormalized",
+            Self::ValueOutOfRange(_) => "ValueOutOfRange",
+            Self::UbeNotFinite(_) => "UbeNotFinite",
+            Self::IllegalMove(_) => "IllegalMove",
+            Self::WrongSize(_) => "WrongSize",
+            Self::Json(..) => "Json",
+        }
+    }
 }
 
+/// A position with its training targets. Datasets may leave out any of them,
+/// such as endgame value labels without a policy.
+#[derive(Clone, Debug)]
 pub struct Target {
     pub tps: Tps,
-    pub value: f32,
+    pub value: Option<f32>,
     pub ube: Option<f32>,
-    pub policy: Box<[(Move, f32)]>,
+    /// An empty policy is the same as a missing one. Both are written as an empty field,
+    /// which is parsed as `None`, so the parsers never return an empty policy.
+    pub policy: Option<Box<[(Move, f32)]>>,
+    /// The visit counts which the policy was normalized from, if the search provides them.
+    pub visits: Option<Visits>,
+    /// Cells of the metadata columns, such as the game ID or the network generation.
+    pub metadata: Vec<(String, Metadata)>,
+    /// The final result of the game which the position is from.
+    pub result: Option<GameResult>,
 }
 
+/// Parses `{tps};{value};{policy}` or `{tps};{value};{ube};{policy}`,
+/// where an empty field is a missing target. Without a policy it may also be `{tps};{value}`.
+/// Metadata follows as a fifth field of `name=cell` pairs, such as `game=12,network="gen-42"`,
+/// and the game result as a sixth field in PTN notation, such as `R-0` or `1/2-1/2`.
+///
+/// A policy field which starts with a number instead of a move has visit counts,
+/// such as `800,a1:500,b2:299` (see [`Visits`]), and the policy is normalized from them.
 impl FromStr for Target {
     type Err = ParseTargetError;
 
@@ -40,7 +145,8 @@ impl FromStr for Target {
         //{tps};{value};{ube};{policy}
         let mut iter = s.trim().split(';');
         let tps: Tps = iter.next().ok_or(ParseTargetError::MissingTps)?.parse()?;
-        let value = iter.next().ok_or(ParseTargetError::MissingValue)?.parse()?;
+        let value = iter.next().ok_or(ParseTargetError::MissingValue)?;
+        let value = present(value).map(str::parse).transpose()?;
 
         let mut maybe_ube = iter.next();
         let mut maybe_policy = iter.next();
@@ -48,34 +154,371 @@ impl FromStr for Target {
             // no UBE
             std::mem::swap(&mut maybe_policy, &mut maybe_ube);
         }
-        let ube = maybe_ube.map(|s| s.parse()).transpose()?;
-
-        let policy: Box<_> = maybe_policy
-            .ok_or(ParseTargetError::MissingPolicy)?
+        let ube = maybe_ube.and_then(present).map(str::parse).transpose()?;
+        let metadata = iter
+            .next()
+            .map_or("", str::trim)
             .split_terminator(',')
             .map(|s| {
-                s.split_once(':')
-                    .ok_or(ParseTargetError::WrongPolicyFormat)
-                    .and_then(|(a, p)| Ok((a.parse()?, p.parse()?)))
+                s.split_once('=')
+                    .ok_or(ParseTargetError::WrongMetadataFormat)
+                    .and_then(|(name, cell)| Ok((name.to_string(), cell.parse()?)))
             })
             .collect::<Result<_, _>>()?;
+        let result = iter
+            .next()
+            .map(str::trim)
+            .and_then(present)
+            .map(str::parse)
+            .transpose()?;
+
+        let maybe_policy = maybe_policy.and_then(present);
+        let visits = maybe_policy
+            .filter(|policy| policy.split(',').next().is_some_and(|s| !s.contains(':')))
+            .map(str::parse::<Visits>)
+            .transpose()?;
+        let policy = match &visits {
+            Some(visits) => Some(visits.policy()),
+            None => maybe_policy
+                .map(|policy| {
+                    policy
+                        .split_terminator(',')
+                        .map(|s| {
+                            s.split_once(':')
+                                .ok_or(ParseTargetError::WrongPolicyFormat)
+                                .and_then(|(a, p)| Ok((a.parse()?, p.parse()?)))
+                        })
+                        .collect::<Result<_, _>>()
+                })
+                .transpose()?,
+        };
         Ok(Target {
             tps,
             value,
             ube,
             policy,
+            visits,
+            metadata,
+            result,
         })
     }
 }
 
+/// `None` for an empty field.
+fn present(field: &str) -> Option<&str> {
+    (!field.is_empty()).then_some(field)
+}
+
+/// Writes the `{tps};{value};{ube};{policy}` form which [`Target::from_str`] parses,
+/// leaving out the UBE if there is none, and the policy field if there is neither,
+/// followed by the metadata and the result if there are any.
+/// The policy is written as its visit counts if there are any.
+///
+/// Floats are written with the shortest representation that parses back exactly,
+/// unless a precision is given, such as `format!("{target:.4}")`.
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision();
+        let float = |f: &mut fmt::Formatter<'_>, x: f32| match precision {
+            Some(precision) => write!(f, "{x:.precision$}"),
+            None => write!(f, "{x}"),
+        };
+
+        write!(f, "{};", self.tps)?;
+        if let Some(value) = self.value {
+            float(f, value)?;
+        }
+        let metadata_field = !self.metadata.is_empty() || self.result.is_some();
+        let ube_field = self.ube.is_some() || metadata_field;
+        if ube_field {
+            f.write_str(";")?;
+        }
+        if let Some(ube) = self.ube {
+            float(f, ube)?;
+        }
+        if self.policy.is_none() && self.visits.is_none() && !ube_field {
+            return Ok(());
+        }
+        f.write_str(";")?;
+        if let Some(visits) = &self.visits {
+            write!(f, "{visits}")?;
+        }
+        let probabilities = self.policy.iter().filter(|_| self.visits.is_none());
+        for (i, (action, probability)) in probabilities.flatten().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{action}:")?;
+            float(f, *probability)?;
+        }
+        if metadata_field {
+            f.write_str(";")?;
+        }
+        for (i, (name, cell)) in self.metadata.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{name}={cell}")?;
+        }
+        if let Some(result) = self.result {
+            write!(f, ";{result}")?;
+        }
+        Ok(())
+    }
+}
+
 impl Target {
+    /// The final result of the game for the side to move, in the same range as the value:
+    /// one for a win, minus one for a loss, and zero for a draw.
+    /// Blend it with the search value, such as `(1.0 - z) * value + z * outcome`.
+    pub fn outcome(&self) -> Option<f32> {
+        let winner = match self.result? {
+            GameResult::White(_) => Color::White,
+            GameResult::Black(_) => Color::Black,
+            GameResult::Draw => return Some(0.0),
+        };
+        Some(if winner == self.tps.color() {
+            1.0
+        } else {
+            -1.0
+        })
+    }
+
+    /// Check that the target has every channel which the header declares,
+    /// and that its metadata fits the columns. Integers in float columns become floats.
+    pub fn check_channels(&mut self, header: &header::Header) -> Result<(), ParseTargetError> {
+        if header.value && self.value.is_none() {
+            return Err(ParseTargetError::MissingValue);
+        }
+        if header.ube && self.ube.is_none() {
+            return Err(ParseTargetError::MissingUbe);
+        }
+        for (name, cell) in &mut self.metadata {
+            let column = header
+                .columns
+                .iter()
+                .find(|column| column.name == *name)
+                .ok_or_else(|| ParseTargetError::UnknownColumn(name.clone()))?;
+            if let (ColumnType::Float, &mut Metadata::Int(int)) = (column.kind, &mut *cell) {
+                *cell = Metadata::Float(int as f32);
+            }
+            if cell.kind() != column.kind {
+                return Err(ParseTargetError::WrongColumnType(name.clone()));
+            }
+        }
+        if header.policy && self.policy.is_none() {
+            return Err(ParseTargetError::MissingPolicy);
+        }
+        if header.visits && self.visits.is_none() {
+            return Err(ParseTargetError::MissingVisits);
+        }
+        Ok(())
+    }
+
     pub fn actions_match_policy(&self, real_actions: &[Move]) -> bool {
-        self.policy.len() == real_actions.len()
-            && self
-                .policy
+        self.policy.as_ref().is_some_and(|policy| {
+            policy.len() == real_actions.len()
+                && policy.iter().zip(real_actions).all(|((a, _), b)| a == b)
+        })
+    }
+
+    /// Map the policy onto the legal moves by move identity,
+    /// so that it lists exactly `real_actions` in the same order.
+    /// Legal moves which the policy leaves out get a probability of zero.
+    pub fn align_policy(&mut self, real_actions: &[Move]) -> Result<Alignment, AlignError> {
+        let Some(policy) = &self.policy else {
+            return Ok(Alignment::Missing);
+        };
+        if self.actions_match_policy(real_actions) {
+            return Ok(Alignment::Exact);
+        }
+        let mut aligned: Vec<(Move, Option<f32>)> =
+            real_actions.iter().map(|&a| (a, None)).collect();
+        for &(action, probability) in policy.iter() {
+            let (_, slot) = aligned
+                .iter_mut()
+                .find(|(a, _)| *a == action)
+                .ok_or(AlignError::IllegalMove(action))?;
+            if slot.replace(probability).is_some() {
+                return Err(AlignError::DuplicateMove(action));
+            }
+        }
+        let missing = aligned.iter().filter(|(_, p)| p.is_none()).count();
+        self.policy = Some(
+            aligned
+                .into_iter()
+                .map(|(a, p)| (a, p.unwrap_or(0.0)))
+                .collect(),
+        );
+        Ok(if missing == 0 {
+            Alignment::Reordered
+        } else {
+            Alignment::Filled(missing)
+        })
+    }
+}
+
+impl Target {
+    /// Replay the TPS and check that every move in the policy is legal
+    /// and that the probabilities are non-negative numbers which sum to one.
+    /// A value outside of [-1, 1] or a UBE which is not finite cannot be stored, so it is an error.
+    pub fn validate<const N: usize, const HALF_KOMI: i8>(
+        &self,
+    ) -> Result<PolicyReport, ParseTargetError>
+    where
+        Reserves<N>: Default,
+    {
+        if self.tps.size() != N {
+            return Err(ParseTargetError::WrongSize(self.tps.size()));
+        }
+        if let Some(value) = self.value.filter(|value| !(-1.0..=1.0).contains(value)) {
+            return Err(ParseTargetError::ValueOutOfRange(value));
+        }
+        if let Some(ube) = self.ube.filter(|ube| !ube.is_finite()) {
+            return Err(ParseTargetError::UbeNotFinite(ube));
+        }
+        let Some(policy) = &self.policy else {
+            return Ok(PolicyReport::default());
+        };
+        let game = Game::<N, HALF_KOMI>::from(self.tps.clone());
+        let mut actions = vec![];
+        game.possible_moves(&mut actions);
+
+        let mut report = PolicyReport {
+            sum: Some(0.0),
+            ..Default::default()
+        };
+        for &(action, probability) in policy.iter() {
+            if !actions.contains(&action) {
+                report.illegal.push(action);
+            }
+            if probability.is_nan() {
+                report.nan.push(action);
+            } else {
+                if probability < 0.0 {
+                    report.negative.push(action);
+                }
+                if probability > 1.0 {
+                    report.above_one.push(action);
+                }
+                report.sum = report.sum.map(|sum| sum + probability);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Drop the illegal moves and the NaN or negative probabilities,
+    /// clamp the probabilities above one, and renormalize the rest.
+    /// The illegal moves are also dropped from the visit counts,
+    /// and their visits are taken off the total, so that the total still covers the counts.
+    /// Returns the problems found before the repair.
+    pub fn repair<const N: usize, const HALF_KOMI: i8>(
+        &mut self,
+    ) -> Result<PolicyReport, ParseTargetError>
+    where
+        Reserves<N>: Default,
+    {
+        let report = self.validate::<N, HALF_KOMI>()?;
+        let Some(policy) = self.policy.as_ref().filter(|_| !report.is_valid()) else {
+            return Ok(report);
+        };
+        let mut policy: Vec<_> = policy
+            .iter()
+            .copied()
+            .filter(|(action, probability)| *probability >= 0.0 && !report.illegal.contains(action))
+            .map(|(action, probability)| (action, probability.min(1.0)))
+            .collect();
+        let sum: f32 = policy.iter().map(|(_, p)| p).sum();
+        if sum <= 0.0 {
+            return Err(ParseTargetError::PolicyUnnormalized(sum));
+        }
+        policy.iter_mut().for_each(|(_, p)| *p /= sum);
+        self.policy = Some(policy.into_boxed_slice());
+        if let Some(visits) = &mut self.visits {
+            let (illegal, legal): (Vec<_>, Vec<_>) = visits
+                .counts
                 .iter()
-                .zip(real_actions)
-                .all(|((a, _), b)| a == b)
+                .partition(|(action, _)| report.illegal.contains(action));
+            let dropped: u32 = illegal.iter().map(|&(_, count)| count).sum();
+            visits.total = visits.total.saturating_sub(dropped);
+            visits.counts = legal.into_boxed_slice();
+        }
+        Ok(report)
+    }
+}
+
+/// Problems with a policy found by [`Target::validate`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolicyReport {
+    /// Moves which cannot be played in the position.
+    pub illegal: Vec<Move>,
+    /// Moves whose probability is NaN.
+    pub nan: Vec<Move>,
+    /// Moves whose probability is negative.
+    pub negative: Vec<Move>,
+    /// Moves whose probability is above one.
+    pub above_one: Vec<Move>,
+    /// Sum of the probabilities which are not NaN, or `None` without a policy.
+    pub sum: Option<f32>,
+}
+
+impl PolicyReport {
+    pub fn is_normalized(&self) -> bool {
+        self.sum
+            .is_none_or(|sum| (sum - 1.0).abs() <= NORMALIZATION_TOLERANCE)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.error().is_none()
+    }
+
+    /// The first problem as an error, checking for illegal moves,
+    /// NaN, negative, and too large probabilities, and the sum in that order.
+    pub fn error(&self) -> Option<ParseTargetError> {
+        if let Some(&action) = self.illegal.first() {
+            Some(ParseTargetError::IllegalMove(action))
+        } else if !self.nan.is_empty() {
+            Some(ParseTargetError::PolicyNan)
+        } else if !self.negative.is_empty() {
+            Some(ParseTargetError::PolicyNegative)
+        } else if !self.above_one.is_empty() {
+            Some(ParseTargetError::PolicyAboveOne)
+        } else {
+            self.sum
+                .filter(|_| !self.is_normalized())
+                .map(ParseTargetError::PolicyUnnormalized)
+        }
+    }
+}
+
+/// How [`Target::align_policy`] mapped a policy onto the legal moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    /// There is no policy.
+    Missing,
+    /// The policy already listed the legal moves in order.
+    Exact,
+    /// The policy listed the legal moves in a different order.
+    Reordered,
+    /// The policy left out this many legal moves.
+    Filled(usize),
+}
+
+#[derive(Error, Debug)]
+pub enum AlignError {
+    #[error("move {0} in the policy is not legal")]
+    IllegalMove(Move),
+    #[error("move {0} is in the policy twice")]
+    DuplicateMove(Move),
+}
+
+impl AlignError {
+    /// The name of the variant, for counting errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::IllegalMove(_) => "IllegalMove",
+            Self::DuplicateMove(_) => "DuplicateMove",
+        }
     }
 }
 
@@ -89,4 +532,259 @@ mod tests {
     fn test_parse_target() {
         let _: Target = EXAMPLE_TARGET.parse().unwrap();
     }
+
+    #[test]
+    fn test_parse_error_kind() {
+        let err = "x6/x6/x6/x6/x6/x6 1 1".parse::<Target>().unwrap_err();
+        assert_eq!(err.kind(), "MissingValue");
+        let err = "x6/x6/x6/x6/x6/x6 1 1;half;a1:1"
+            .parse::<Target>()
+            .unwrap_err();
+        assert_eq!(err.kind(), "Float");
+    }
+
+    fn assert_round_trip(target: &Target) {
+        let parsed: Target = target.to_string().parse().unwrap();
+        assert_eq!(parsed.tps, target.tps);
+        assert_eq!(
+            parsed.value.map(f32::to_bits),
+            target.value.map(f32::to_bits)
+        );
+        assert_eq!(parsed.ube.map(f32::to_bits), target.ube.map(f32::to_bits));
+        assert_eq!(parsed.metadata, target.metadata);
+        assert_eq!(parsed.result, target.result);
+        let (Some(parsed), Some(target)) = (&parsed.policy, &target.policy) else {
+            assert_eq!(parsed.policy.is_none(), target.policy.is_none());
+            return;
+        };
+        assert_eq!(parsed.len(), target.len());
+        for ((a, p), (b, q)) in parsed.iter().zip(target.iter()) {
+            assert_eq!(a, b);
+            assert_eq!(p.to_bits(), q.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_align_policy() {
+        let actions: Vec<Move> = ["a1", "b1", "c1"]
+            .into_iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let mut target: Target = "x3/x3/x3 1 1;0;c1:0.5,a1:0.25,b1:0.25".parse().unwrap();
+        assert_eq!(target.align_policy(&actions).unwrap(), Alignment::Reordered);
+        assert_eq!(target.align_policy(&actions).unwrap(), Alignment::Exact);
+        assert_eq!(target.policy.as_ref().unwrap()[2], (actions[2], 0.5));
+
+        let mut target: Target = "x3/x3/x3 1 1;0;b1:1".parse().unwrap();
+        assert_eq!(target.align_policy(&actions).unwrap(), Alignment::Filled(2));
+        assert_eq!(target.policy.as_ref().unwrap()[0], (actions[0], 0.0));
+
+        let mut target: Target = "x3/x3/x3 1 1;0;a1:0.5,a2:0.5".parse().unwrap();
+        assert!(matches!(
+            target.align_policy(&actions),
+            Err(AlignError::IllegalMove(_))
+        ));
+
+        let mut target: Target = "x3/x3/x3 1 1;0".parse().unwrap();
+        assert_eq!(target.align_policy(&actions).unwrap(), Alignment::Missing);
+    }
+
+    #[test]
+    fn test_validate_and_repair() {
+        // White has no capstone left and the stack on a1 is taller than the carry limit.
+        let mut target: Target =
+            "x5/x5/x5/x5/212121C,x4 1 7;0;Cb1:0.5,6a1>:0.25,b1:NaN,c1:-0.1,a2:0.25"
+                .parse()
+                .unwrap();
+        let report = target.validate::<5, 0>().unwrap();
+        assert_eq!(report.illegal.len(), 2);
+        assert_eq!(report.nan, ["b1".parse::<Move>().unwrap()]);
+        assert_eq!(report.negative, ["c1".parse::<Move>().unwrap()]);
+        assert!(matches!(
+            report.error(),
+            Some(ParseTargetError::IllegalMove(_))
+        ));
+
+        target.repair::<5, 0>().unwrap();
+        assert_eq!(
+            target.policy.as_deref(),
+            Some(&[("a2".parse().unwrap(), 1.0)][..])
+        );
+        assert!(target.validate::<5, 0>().unwrap().is_valid());
+        assert!(matches!(
+            target.validate::<4, 0>(),
+            Err(ParseTargetError::WrongSize(5))
+        ));
+
+        let mut target: Target = "x3/x3/x3 1 1;0;a1:1.5,b1:0.5".parse().unwrap();
+        let report = target.validate::<3, 0>().unwrap();
+        assert_eq!(report.above_one, ["a1".parse::<Move>().unwrap()]);
+        assert_eq!(report.error().unwrap().kind(), "PolicyAboveOne");
+        target.repair::<3, 0>().unwrap();
+        let policy = target.policy.as_deref().unwrap();
+        assert_eq!((policy[0].1, policy[1].1), (2.0 / 3.0, 1.0 / 3.0));
+
+        for (line, kind) in [
+            ("x3/x3/x3 1 1;1.5;a1:1", "ValueOutOfRange"),
+            ("x3/x3/x3 1 1;NaN;a1:1", "ValueOutOfRange"),
+            ("x3/x3/x3 1 1;0.5;inf;a1:1", "UbeNotFinite"),
+        ] {
+            let mut target: Target = line.parse().unwrap();
+            let err = target.validate::<3, 0>().unwrap_err();
+            assert_eq!(err.kind(), kind);
+            assert!(target.repair::<3, 0>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let target: Target = EXAMPLE_TARGET.parse().unwrap();
+        assert_round_trip(&target);
+        assert_eq!(target.to_string(), EXAMPLE_TARGET);
+
+        let rounded: Target = format!("{target:.3}").parse().unwrap();
+        assert_eq!(rounded.value, Some(0.592));
+        assert_eq!(rounded.ube, Some(3.627));
+    }
+
+    #[test]
+    fn test_missing_targets() {
+        for line in [
+            "x3/x3/x3 1 1;0.5",
+            "x3/x3/x3 1 1;;a1:1",
+            "x3/x3/x3 1 1;0.5;2;",
+            "x3/x3/x3 1 1;",
+        ] {
+            let target: Target = line.parse().unwrap();
+            assert_eq!(target.to_string(), line);
+            assert_round_trip(&target);
+        }
+        let target: Target = "x3/x3/x3 1 1;;a1:1".parse().unwrap();
+        assert_eq!(target.value, None);
+        assert!(target.policy.is_some());
+
+        let empty = Target::from_json(r#"{"tps":"x3/x3/x3 1 1","value":0.5,"policy":{}}"#).unwrap();
+        assert!(empty.policy.is_none());
+        assert_eq!(empty.to_string(), "x3/x3/x3 1 1;0.5");
+        assert_round_trip(&empty);
+        assert_eq!(Target::from_json(&empty.to_json()).unwrap().policy, None);
+    }
+
+    #[test]
+    fn test_metadata_field() {
+        let line = r#"x3/x3/x3 1 1;0.5;;a1:1;game=12,temperature=1,network="gen-42""#;
+        let mut target: Target = line.parse().unwrap();
+        assert_eq!(target.to_string(), line);
+        assert_eq!(target.metadata[0], ("game".to_string(), Metadata::Int(12)));
+
+        // Strings are escaped so that the separators inside them survive the round trip.
+        let json = r#"{"tps":"x3/x3/x3 1 1","value":0.5,"metadata":{"net":"a,b;c \"d\" \\e"}}"#;
+        let escaped = Target::from_json(json).unwrap();
+        assert_eq!(
+            escaped.to_string(),
+            r#"x3/x3/x3 1 1;0.5;;;net="a\u002cb\u003bc \"d\" \\e""#
+        );
+        assert_round_trip(&escaped);
+        assert!("x3/x3/x3 1 1;0.5;;;net=\"a".parse::<Target>().is_err());
+
+        let mut header = header::Header {
+            columns: ["game:int", "temperature:float", "network:str"]
+                .into_iter()
+                .map(|column| column.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        target.check_channels(&header).unwrap();
+        assert_eq!(target.metadata[1].1, Metadata::Float(1.0));
+        assert_round_trip(&target);
+
+        header.columns.pop();
+        assert!(matches!(
+            target.check_channels(&header),
+            Err(ParseTargetError::UnknownColumn(_))
+        ));
+        let err = "x3/x3/x3 1 1;0.5;;;ply".parse::<Target>().unwrap_err();
+        assert_eq!(err.kind(), "WrongMetadataFormat");
+    }
+
+    #[test]
+    fn test_visits_field() {
+        let line = "x3/x3/x3 1 1;0.5;4,a1:3,b2:1";
+        let mut target: Target = line.parse().unwrap();
+        assert_eq!(target.to_string(), line);
+        assert_eq!(target.policy.as_ref().unwrap()[0].1, 0.75);
+        assert_eq!(target.visits.as_ref().unwrap().total, 4);
+
+        let header = header::Header {
+            visits: true,
+            ..Default::default()
+        };
+        target.check_channels(&header).unwrap();
+        target.visits = None;
+        assert!(matches!(
+            target.check_channels(&header),
+            Err(ParseTargetError::MissingVisits)
+        ));
+        let err = "x3/x3/x3 1 1;0.5;4,a1:0.5".parse::<Target>().unwrap_err();
+        assert_eq!(err.kind(), "Int");
+
+        // There are no capstones on 3x3.
+        let mut target: Target = "x3/x3/x3 1 1;0.5;10,a1:6,Ca1:3,b2:1".parse().unwrap();
+        assert_eq!(target.repair::<3, 0>().unwrap().illegal.len(), 1);
+        assert_eq!(target.to_string(), "x3/x3/x3 1 1;0.5;7,a1:6,b2:1");
+        assert!((target.policy.as_ref().unwrap()[0].1 - 6.0 / 7.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_result_field() {
+        let line = "x3/x3/x3 2 1;0.5;;a1:1;;R-0";
+        let target: Target = line.parse().unwrap();
+        assert_eq!(target.to_string(), line);
+        assert_eq!(
+            target.result,
+            Some(GameResult::White(takparse::WinReason::Road))
+        );
+        assert_eq!(target.outcome(), Some(-1.0));
+
+        let target: Target = "x3/x3/x3 1 1;0.5;;a1:1;;1/2-1/2".parse().unwrap();
+        assert_eq!(target.outcome(), Some(0.0));
+        let err = "x3/x3/x3 1 1;0.5;;a1:1;;2-0".parse::<Target>().unwrap_err();
+        assert_eq!(err.kind(), "GameResult");
+    }
+
+    #[test]
+    fn test_display_round_trip_generated() {
+        use fast_tak::{Game, GameResult};
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut actions = vec![];
+        for _ in 0..20 {
+            let mut game = Game::<5, 4>::default();
+            while game.result() == GameResult::Ongoing && game.ply < 60 {
+                actions.clear();
+                game.possible_moves(&mut actions);
+                let target = Target {
+                    tps: game.clone().into(),
+                    value: rng.gen_bool(0.9).then(|| rng.gen_range(-1.0..=1.0)),
+                    ube: rng.gen_bool(0.5).then(|| rng.gen::<f32>() * 10.0),
+                    policy: rng.gen_bool(0.9).then(|| {
+                        actions
+                            .iter()
+                            .map(|&action| (action, rng.gen::<f32>().powi(8)))
+                            .collect()
+                    }),
+                    visits: None,
+                    metadata: rng
+                        .gen_bool(0.5)
+                        .then(|| ("ply".to_string(), Metadata::Int(game.ply.into())))
+                        .into_iter()
+                        .collect(),
+                    result: rng.gen_bool(0.5).then_some(takparse::GameResult::Draw),
+                };
+                assert_round_trip(&target);
+                game.play(*actions.choose(&mut rng).unwrap()).unwrap();
+            }
+        }
+    }
 }
@@ -0,0 +1,205 @@
+//! Zero-copy decoding of memory-mapped compressed files.
+//!
+//! The [`SliceDecoder`] reads records directly from a byte slice with a cursor,
+//! so several threads can decode different block ranges of one mapping.
+
+use std::{
+    fs::File,
+    io,
+    ops::{Deref, Range},
+    path::Path,
+};
+
+use fast_tak::Reserves;
+use memmap2::Mmap;
+
+use crate::{
+    codec::{DecodeError, Record, RecordReader},
+    header::Header,
+    index::Index,
+};
+
+/// A read-only memory mapping of a compressed file.
+pub struct MappedFile {
+    mmap: Mmap,
+}
+
+impl MappedFile {
+    /// Map the file into memory.
+    ///
+    /// The file must not be modified while it is mapped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: The mapping is read-only, and we document that the file
+        // must not be changed by other processes while it is mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.mmap
+    }
+}
+
+/// Reads records from a byte slice, keeping track of the state for relative records.
+pub struct SliceDecoder<'a, const N: usize> {
+    bytes: &'a [u8],
+    header: Header,
+    position: usize,
+    record_start: usize,
+    reader: RecordReader<N>,
+}
+
+impl<'a, const N: usize> SliceDecoder<'a, N>
+where
+    Reserves<N>: Default,
+{
+    /// Decoder for a whole file, which starts with the header if there is one.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        let (header, len) = Header::detect(bytes)?;
+        let mut decoder = Self::with_header(bytes, header);
+        decoder.position = len;
+        Ok(decoder)
+    }
+
+    /// The slice must start with a record which stores its state in full.
+    pub fn with_header(bytes: &'a [u8], header: Header) -> Self {
+        Self {
+            bytes,
+            header,
+            position: 0,
+            record_start: 0,
+            reader: RecordReader::new(),
+        }
+    }
+
+    /// Decoder for a range of blocks, which can be read independently of the rest.
+    pub fn blocks(bytes: &'a [u8], index: &Index, blocks: Range<usize>) -> Self {
+        let start = if blocks.start < index.blocks() {
+            index.keyframe(blocks.start).offset as usize
+        } else {
+            bytes.len()
+        };
+        let end = if blocks.end < index.blocks() {
+            index.keyframe(blocks.end).offset as usize
+        } else {
+            bytes.len()
+        };
+        Self::with_header(&bytes[start..end.max(start)], index.header().clone())
+    }
+
+    /// Read the next record, or `None` at the end of the slice.
+    pub fn read_record(&mut self) -> Result<Option<Record<N>>, DecodeError> {
+        if self.position >= self.bytes.len() {
+            return Ok(None);
+        }
+        self.record_start = self.position;
+        let mut input = io::Cursor::new(self.bytes);
+        input.set_position(self.position as u64);
+        let record = self.reader.read(&mut input, &self.header)?;
+        self.position = input.position() as usize;
+        Ok(Some(record))
+    }
+}
+
+impl<'a, const N: usize> SliceDecoder<'a, N> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Byte offset of the last record in the slice.
+    pub fn offset(&self) -> usize {
+        self.record_start
+    }
+
+    /// The raw bytes of the last record.
+    pub fn raw(&self) -> &'a [u8] {
+        &self.bytes[self.record_start..self.position]
+    }
+
+    /// The raw bytes of the channels of the last record, including its metadata
+    /// but not the result.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.bytes[self.reader.payload_start..self.position]
+    }
+}
+
+impl<const N: usize> Iterator for SliceDecoder<'_, N>
+where
+    Reserves<N>: Default,
+{
+    type Item = Result<Record<N>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use fast_tak::Game;
+
+    use super::*;
+    use crate::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_parallel_blocks() {
+        let mut bytes = vec![];
+        let mut encoder = Encoder::<6>::new();
+        for moves in [&["a1", "f6", "c3"][..], &["b2", "e5"], &["d4", "a6", "Sc3"]] {
+            for ply in 1..=moves.len() {
+                let game = Game::<6, 4>::from_ptn_moves(&moves[..ply]);
+                let policy = vec![("c4".parse().unwrap(), 0.5)];
+                encoder
+                    .write_record(&mut bytes, &Record::new(game, ply as f32 / 4.0, policy))
+                    .unwrap();
+            }
+            encoder.reset();
+        }
+
+        let index = Index::build::<6>(bytes.as_slice()).unwrap();
+        assert_eq!(index.blocks(), 3);
+        let expected: Vec<_> = Decoder::<6, _>::new(bytes.as_slice())
+            .map(|record| record.unwrap().state.board)
+            .collect();
+
+        let decoded: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = [0..1, 1..3]
+                .into_iter()
+                .map(|blocks| {
+                    let decoder = SliceDecoder::<6>::blocks(&bytes, &index, blocks);
+                    s.spawn(move || {
+                        decoder
+                            .map(|record| record.unwrap().state.board)
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        assert_eq!(decoded, expected);
+
+        // Both decoders split the records into the same bytes.
+        let mut decoder = Decoder::<6, _>::new(bytes.as_slice());
+        let mut slice_decoder = SliceDecoder::<6>::new(&bytes).unwrap();
+        while let Some(record) = decoder.read_record().unwrap() {
+            let slice_record = slice_decoder.read_record().unwrap().unwrap();
+            assert_eq!(
+                slice_record.to_target().to_string(),
+                record.to_target().to_string()
+            );
+            assert_eq!(slice_decoder.raw(), decoder.raw());
+            assert_eq!(slice_decoder.payload(), decoder.payload());
+        }
+        assert!(slice_decoder.read_record().unwrap().is_none());
+    }
+}
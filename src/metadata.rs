@@ -0,0 +1,380 @@
+//! Typed metadata columns, such as the game ID, ply, or network generation of a target.
+//!
+//! The [`Header`](crate::header::Header) declares the columns, and every record stores
+//! a cell for each of them after its other channels. A cell may be missing.
+//! Each column is compressed relative to its last value in the previous records,
+//! which is forgotten at every record with a full state so that blocks can be decoded on their own:
+//!
+//! - integers are the zigzag-encoded difference to the last value plus one as a varint,
+//! - floats are a tag byte, followed by the `f32` unless it repeats the last value,
+//! - strings are a varint which is one if the string repeats the last value,
+//!   and otherwise its length plus two, followed by the UTF-8 bytes.
+//!
+//! In all cases zero marks a missing cell.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use crate::{
+    codec::{read_byte, DecodeError},
+    json, ParseTargetError,
+};
+
+/// The type of the cells of a column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Str,
+}
+
+impl ColumnType {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Self::Int => 0,
+            Self::Float => 1,
+            Self::Str => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Int),
+            1 => Some(Self::Float),
+            2 => Some(Self::Str),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" => Ok(Self::Int),
+            "float" => Ok(Self::Float),
+            "str" => Ok(Self::Str),
+            _ => Err(format!("unknown column type {s}")),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Str => "str",
+        })
+    }
+}
+
+/// A named column of a [`Header`](crate::header::Header).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+/// Parses `name:type`, such as `game_id:int`.
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, kind) = s
+            .split_once(':')
+            .ok_or_else(|| format!("column {s} has no type"))?;
+        Ok(Self {
+            name: name.to_string(),
+            kind: kind.parse()?,
+        })
+    }
+}
+
+/// The value of a cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metadata {
+    Int(i64),
+    Float(f32),
+    Str(String),
+}
+
+impl Metadata {
+    pub fn kind(&self) -> ColumnType {
+        match self {
+            Self::Int(_) => ColumnType::Int,
+            Self::Float(_) => ColumnType::Float,
+            Self::Str(_) => ColumnType::Str,
+        }
+    }
+}
+
+/// Parses the text form written by [`Metadata`]'s `Display`:
+/// a quoted and escaped string, an integer, or a float.
+impl FromStr for Metadata {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('"') {
+            Ok(Self::Str(json::parse_string(s)?))
+        } else if let Ok(int) = s.parse() {
+            Ok(Self::Int(int))
+        } else {
+            Ok(Self::Float(s.parse()?))
+        }
+    }
+}
+
+/// Floats always have a decimal point or an exponent, so that they parse back as floats.
+/// Strings are written as JSON strings, where `,` and `;` are also escaped as `\u002c`
+/// and `\u003b`, so that they are not taken for the separators of the text format.
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(int) => write!(f, "{int}"),
+            Self::Float(float) => write!(f, "{float:?}"),
+            Self::Str(string) => json::write_string(f, string, &[',', ';']),
+        }
+    }
+}
+
+/// Encodes and decodes the metadata of consecutive records,
+/// remembering the last value of every column.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataCoder {
+    last: Vec<Option<Metadata>>,
+}
+
+/// Coders are equal if they would encode the next record the same way.
+impl PartialEq for MetadataCoder {
+    fn eq(&self, other: &Self) -> bool {
+        let len = self.last.len().max(other.last.len());
+        let padded = |last: &[Option<Metadata>], i: usize| last.get(i).cloned().flatten();
+        (0..len).all(|i| padded(&self.last, i) == padded(&other.last, i))
+    }
+}
+
+impl MetadataCoder {
+    /// Forget the last values, as at a record with a full state.
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+
+    fn last(&mut self, columns: usize) -> &mut [Option<Metadata>] {
+        self.last.resize(columns, None);
+        &mut self.last
+    }
+
+    /// Write a cell for every column, failing on cells of undeclared columns or of the wrong type.
+    pub fn write(
+        &mut self,
+        output: &mut impl Write,
+        columns: &[Column],
+        metadata: &[(String, Metadata)],
+    ) -> io::Result<usize> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        for (name, cell) in metadata {
+            let column = columns
+                .iter()
+                .find(|column| column.name == *name)
+                .ok_or_else(|| invalid(format!("the header has no column {name}")))?;
+            if cell.kind() != column.kind {
+                return Err(invalid(format!(
+                    "column {name} is {}, but the cell is {}",
+                    column.kind,
+                    cell.kind()
+                )));
+            }
+        }
+
+        let mut bytes = vec![];
+        for (column, last) in columns.iter().zip(self.last(columns.len())) {
+            let cell = metadata
+                .iter()
+                .find(|(name, _)| *name == column.name)
+                .map(|(_, cell)| cell);
+            match (column.kind, cell) {
+                (_, None) => write_varint(&mut bytes, 0),
+                (ColumnType::Int, Some(&Metadata::Int(int))) => {
+                    let previous = match last {
+                        Some(Metadata::Int(previous)) => *previous,
+                        _ => 0,
+                    };
+                    let delta = i128::from(int) - i128::from(previous);
+                    write_varint(&mut bytes, zigzag(delta) + 1);
+                }
+                (ColumnType::Float, Some(&Metadata::Float(float))) => match last {
+                    Some(Metadata::Float(previous)) if previous.to_bits() == float.to_bits() => {
+                        bytes.push(1);
+                    }
+                    _ => {
+                        bytes.push(2);
+                        bytes.extend(float.to_le_bytes());
+                    }
+                },
+                (ColumnType::Str, Some(Metadata::Str(string))) => match last {
+                    Some(Metadata::Str(previous)) if previous == string => {
+                        write_varint(&mut bytes, 1);
+                    }
+                    _ => {
+                        write_varint(&mut bytes, string.len() as u128 + 2);
+                        bytes.extend_from_slice(string.as_bytes());
+                    }
+                },
+                (_, Some(_)) => unreachable!("the types were checked"),
+            }
+            if let Some(cell) = cell {
+                *last = Some(cell.clone());
+            }
+        }
+        output.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Read a cell for every column, leaving out the missing ones.
+    pub fn read(
+        &mut self,
+        input: &mut impl Read,
+        columns: &[Column],
+    ) -> Result<Vec<(String, Metadata)>, DecodeError> {
+        let invalid = DecodeError::InvalidMetadata;
+        let mut metadata = vec![];
+        for (column, last) in columns.iter().zip(self.last(columns.len())) {
+            let cell = match column.kind {
+                ColumnType::Int => match read_varint(input)? {
+                    0 => None,
+                    code => {
+                        let previous = match last {
+                            Some(Metadata::Int(previous)) => *previous,
+                            _ => 0,
+                        };
+                        let int = i64::try_from(i128::from(previous) + unzigzag(code - 1))
+                            .map_err(|_| invalid("integer out of range"))?;
+                        Some(Metadata::Int(int))
+                    }
+                },
+                ColumnType::Float => match read_byte(input)? {
+                    0 => None,
+                    1 => match last {
+                        Some(Metadata::Float(previous)) => Some(Metadata::Float(*previous)),
+                        _ => return Err(invalid("repeated float without a previous one")),
+                    },
+                    2 => {
+                        let mut bytes = [0; 4];
+                        input.read_exact(&mut bytes)?;
+                        Some(Metadata::Float(f32::from_le_bytes(bytes)))
+                    }
+                    _ => return Err(invalid("unknown float tag")),
+                },
+                ColumnType::Str => match read_varint(input)? {
+                    0 => None,
+                    1 => match last {
+                        Some(Metadata::Str(previous)) => Some(Metadata::Str(previous.clone())),
+                        _ => return Err(invalid("repeated string without a previous one")),
+                    },
+                    code => {
+                        let len =
+                            usize::try_from(code - 2).map_err(|_| invalid("string is too long"))?;
+                        let mut bytes = vec![0; len];
+                        input.read_exact(&mut bytes)?;
+                        let string =
+                            String::from_utf8(bytes).map_err(|_| invalid("string is not UTF-8"))?;
+                        Some(Metadata::Str(string))
+                    }
+                },
+            };
+            if let Some(cell) = cell {
+                *last = Some(cell.clone());
+                metadata.push((column.name.clone(), cell));
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+fn zigzag(x: i128) -> u128 {
+    ((x << 1) ^ (x >> 127)) as u128
+}
+
+fn unzigzag(x: u128) -> i128 {
+    (x >> 1) as i128 ^ -((x & 1) as i128)
+}
+
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut x: u128) {
+    while x >= 0x80 {
+        bytes.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+pub(crate) fn read_varint(input: &mut impl Read) -> Result<u128, DecodeError> {
+    let mut x = 0;
+    for shift in (0..128).step_by(7) {
+        let byte = read_byte(input)?;
+        x |= u128::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let columns: Vec<Column> = ["game:int", "ply:int", "temperature:float", "network:str"]
+            .into_iter()
+            .map(|column| column.parse().unwrap())
+            .collect();
+        let rows = [
+            vec![
+                ("game".to_string(), Metadata::Int(i64::MAX)),
+                ("ply".to_string(), Metadata::Int(0)),
+                ("network".to_string(), Metadata::Str("gen-42".to_string())),
+            ],
+            vec![
+                ("game".to_string(), Metadata::Int(i64::MIN)),
+                ("ply".to_string(), Metadata::Int(1)),
+                ("temperature".to_string(), Metadata::Float(0.5)),
+                ("network".to_string(), Metadata::Str("gen-42".to_string())),
+            ],
+        ];
+
+        let mut bytes = vec![];
+        let mut encoder = MetadataCoder::default();
+        for row in &rows {
+            encoder.write(&mut bytes, &columns, row).unwrap();
+        }
+        let mut decoder = MetadataCoder::default();
+        let mut input = bytes.as_slice();
+        for row in &rows {
+            assert_eq!(&decoder.read(&mut input, &columns).unwrap(), row);
+        }
+        assert!(input.is_empty());
+
+        let wrong_type = [("ply".to_string(), Metadata::Float(1.0))];
+        assert!(encoder.write(&mut vec![], &columns, &wrong_type).is_err());
+        let undeclared = [("visits".to_string(), Metadata::Int(1))];
+        assert!(encoder.write(&mut vec![], &columns, &undeclared).is_err());
+    }
+
+    #[test]
+    fn test_metadata_text() {
+        for (text, cell) in [
+            ("12", Metadata::Int(12)),
+            ("1.0", Metadata::Float(1.0)),
+            ("\"gen-42\"", Metadata::Str("gen-42".to_string())),
+        ] {
+            assert_eq!(text.parse::<Metadata>().unwrap(), cell);
+            assert_eq!(cell.to_string(), text);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Minimal writer for NumPy `.npy` arrays and uncompressed `.npz` archives.
+//!
+//! Arrays are streamed to disk one row at a time. The header reserves room
+//! for the shape, which is filled in when the array is finished.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+/// Total size of the magic string, header length, and header dictionary.
+const HEADER_SIZE: usize = 128;
+
+/// Element types which can be stored in an array.
+pub trait Element: Copy {
+    /// NumPy type description, such as `<f4`.
+    const DESCR: &'static str;
+    fn write_le(self, output: &mut impl Write) -> io::Result<()>;
+}
+
+macro_rules! element {
+    ($($t:ty => $descr:literal),*) => {
+        $(
+            impl Element for $t {
+                const DESCR: &'static str = $descr;
+                fn write_le(self, output: &mut impl Write) -> io::Result<()> {
+                    output.write_all(&self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+element![u8 => "|u1", u32 => "<u4", i64 => "<i8", f32 => "<f4"];
+
+/// Streams elements into an `.npy` file.
+pub struct NpyWriter<T> {
+    output: BufWriter<File>,
+    elements: u64,
+    _element: PhantomData<T>,
+}
+
+impl<T: Element> NpyWriter<T> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut output = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?,
+        );
+        output.write_all(&[0; HEADER_SIZE])?; // placeholder for the header
+        Ok(Self {
+            output,
+            elements: 0,
+            _element: PhantomData,
+        })
+    }
+
+    pub fn write(&mut self, elements: &[T]) -> io::Result<()> {
+        for &element in elements {
+            element.write_le(&mut self.output)?;
+        }
+        self.elements += elements.len() as u64;
+        Ok(())
+    }
+
+    /// Write the header. The shape of the array is `[rows, row_shape...]`,
+    /// where the number of rows follows from the number of elements written.
+    pub fn finish(mut self, row_shape: &[usize]) -> io::Result<()> {
+        let row_len: u64 = row_shape.iter().map(|&d| d as u64).product();
+        assert_eq!(
+            self.elements % row_len.max(1),
+            0,
+            "the elements do not fill whole rows"
+        );
+        let rows = self.elements / row_len.max(1);
+        let mut shape = format!("{rows},");
+        for d in row_shape {
+            shape.push_str(&format!(" {d},"));
+        }
+        if !row_shape.is_empty() {
+            shape.pop(); // the trailing comma is only needed for 1-tuples
+        }
+        let mut dict = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({shape}), }}",
+            T::DESCR
+        );
+        let dict_size = HEADER_SIZE - MAGIC.len() - 2;
+        assert!(dict.len() < dict_size, "the shape does not fit the header");
+        dict.extend(std::iter::repeat_n(' ', dict_size - dict.len() - 1));
+        dict.push('\n');
+
+        self.output.flush()?;
+        let mut file = self.output.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(MAGIC)?;
+        file.write_all(&(dict_size as u16).to_le_bytes())?;
+        file.write_all(dict.as_bytes())
+    }
+}
+
+/// Pack `.npy` files into an uncompressed `.npz` archive and remove them.
+pub fn pack_npz(output: impl AsRef<Path>, arrays: &[PathBuf]) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    for path in arrays {
+        let name = path
+            .file_name()
+            .expect("The array should be a file")
+            .to_string_lossy();
+        zip.start_file(name, options)?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+    zip.finish()?;
+    for path in arrays {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npy_header() {
+        let path = std::env::temp_dir().join("compress-targets-test-npy-header.npy");
+        let mut writer = NpyWriter::<f32>::create(&path).unwrap();
+        writer.write(&[0.5; 12]).unwrap();
+        writer.finish(&[2, 3]).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 12 * 4);
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+        let header = std::str::from_utf8(&bytes[MAGIC.len() + 2..HEADER_SIZE]).unwrap();
+        assert!(
+            header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 2, 3), }")
+        );
+        assert!(header.ends_with('\n'));
+    }
+}
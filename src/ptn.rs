@@ -0,0 +1,159 @@
+//! Targets from PTN games annotated by the engine.
+//!
+//! The comment on the game (before the first move) belongs to the starting position,
+//! and the comment on each move belongs to the position after it.
+//! A target comment uses the [`Target`] grammar without the TPS,
+//! so `{value;policy}` or `{value;ube;policy}`, such as `{0.25;a1:0.75,b2:0.25}`,
+//! or only `{value}` without a policy.
+//! Other comments are ignored. The targets get the result of the game, if it has one.
+
+use fast_tak::{Game, PlayError, Reserves};
+use takparse::{GameResult, Move, ParsePtnError, Ptn, Tps};
+use thiserror::Error;
+
+use crate::Target;
+
+#[derive(Error, Debug)]
+pub enum PtnError {
+    #[error("{0}")]
+    Parse(#[from] ParsePtnError),
+    #[error("the game is played on size {0}")]
+    WrongSize(usize),
+    #[error("the game has komi {0}, but only a komi of 2 is supported")]
+    WrongKomi(String),
+    #[error("move {0} cannot be played: {1}")]
+    IllegalMove(Move, PlayError),
+}
+
+impl PtnError {
+    /// The name of the variant, for counting errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Parse(_) => "Parse",
+            Self::WrongSize(_) => "WrongSize",
+            Self::WrongKomi(_) => "WrongKomi",
+            Self::IllegalMove(..) => "IllegalMove",
+        }
+    }
+}
+
+/// Split a file with several PTN games into the individual games.
+/// A game starts at a tag which follows the moves of the previous game.
+pub fn split_games(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let mut in_moves = false;
+        let mut end = rest.len();
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                if in_moves {
+                    end = offset;
+                    break;
+                }
+            } else if !trimmed.is_empty() {
+                in_moves = true;
+            }
+            offset += line.len();
+        }
+        let (game, next) = rest.split_at(end);
+        rest = next;
+        (!game.trim().is_empty()).then_some(game)
+    })
+}
+
+/// Replay a game and return every position together with its target, if it has one.
+pub fn annotated_positions<const N: usize>(
+    ptn: &Ptn,
+) -> Result<Vec<(Game<N, 4>, Option<Target>)>, PtnError>
+where
+    Reserves<N>: Default,
+{
+    if let Some(size) = ptn.size() {
+        if size != N {
+            return Err(PtnError::WrongSize(size));
+        }
+    }
+    if let Some(komi) = ptn.get_tag("Komi") {
+        if komi.parse::<f32>().ok() != Some(2.0) {
+            return Err(PtnError::WrongKomi(komi.to_string()));
+        }
+    }
+
+    let mut game = match ptn.tps() {
+        Some(tps) => Game::from(tps),
+        None => Game::default(),
+    };
+    let result = ptn.result();
+    let mut positions = Vec::with_capacity(ptn.moves().len() + 1);
+    positions.push((game.clone(), target(&game, &ptn.comments()[0], result)));
+    for (&action, comments) in ptn.moves().iter().zip(&ptn.comments()[1..]) {
+        game.play(action)
+            .map_err(|err| PtnError::IllegalMove(action, err))?;
+        positions.push((game.clone(), target(&game, comments, result)));
+    }
+    Ok(positions)
+}
+
+/// The target from the first comment which has the target grammar
+/// and a value or a policy. It gets the result of the game unless the comment has one.
+fn target<const N: usize>(
+    game: &Game<N, 4>,
+    comments: &[String],
+    result: Option<GameResult>,
+) -> Option<Target> {
+    let tps = Tps::from(game.clone());
+    comments.iter().find_map(|comment| {
+        format!("{tps};{}", comment.trim())
+            .parse::<Target>()
+            .ok()
+            .filter(|target| target.value.is_some() || target.policy.is_some())
+            .map(|target| Target {
+                result: target.result.or(result),
+                ..target
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = r#"[Size "5"]
+[Komi "2"]
+[Result "R-0"]
+
+{0.5;a1:0.5,e5:0.5}
+1. a1 {-0.25;a2:1} e5 {good move}
+2. a2 {0.75;0.1;b2:1}
+
+[Size "5"]
+1. c3 d3
+"#;
+
+    #[test]
+    fn test_annotated_positions() {
+        let games: Vec<_> = split_games(GAMES).collect();
+        assert_eq!(games.len(), 2);
+
+        let ptn: Ptn = games[0].parse().unwrap();
+        let positions = annotated_positions::<5>(&ptn).unwrap();
+        assert_eq!(positions.len(), 4);
+        let values: Vec<_> = positions
+            .iter()
+            .map(|(_, target)| target.as_ref().and_then(|target| target.value))
+            .collect();
+        assert_eq!(values, [Some(0.5), Some(-0.25), None, Some(0.75)]);
+        assert!(positions[2].1.is_none());
+        assert_eq!(positions[3].1.as_ref().unwrap().ube, Some(0.1));
+        assert_eq!(positions[3].1.as_ref().unwrap().result, ptn.result());
+        assert!(ptn.result().is_some());
+
+        let ptn: Ptn = games[1].parse().unwrap();
+        assert!(matches!(
+            annotated_positions::<6>(&ptn),
+            Err(PtnError::WrongSize(5))
+        ));
+    }
+}
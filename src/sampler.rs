@@ -0,0 +1,125 @@
+//! Uniformly random minibatches from a compressed file.
+
+use std::{
+    collections::VecDeque,
+    io::{BufReader, Read, Seek, SeekFrom},
+    rc::Rc,
+};
+
+use fast_tak::Reserves;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    codec::{DecodeError, Decoder},
+    index::Index,
+    Target,
+};
+
+/// Draws random records, decoding only the blocks which contain them.
+pub struct Sampler<const N: usize, R> {
+    input: R,
+    index: Index,
+    rng: StdRng,
+    /// Recently decoded blocks, most recent last.
+    cache: VecDeque<(usize, Rc<[Target]>)>,
+    cache_size: usize,
+}
+
+impl<const N: usize, R: Read + Seek> Sampler<N, R>
+where
+    Reserves<N>: Default,
+{
+    /// Create a sampler which keeps up to `cache_size` decoded blocks in memory.
+    pub fn new(input: R, index: Index, seed: u64, cache_size: usize) -> Self {
+        assert!(index.records() > 0, "cannot sample from an empty file");
+        Self {
+            input,
+            index,
+            rng: StdRng::seed_from_u64(seed),
+            cache: VecDeque::new(),
+            cache_size: cache_size.max(1),
+        }
+    }
+
+    /// Decode a single record.
+    pub fn get(&mut self, record: u64) -> Result<Target, DecodeError> {
+        let block = self.index.block_of(record);
+        let first = self.index.keyframe(block).record;
+        let targets = self.block(block)?;
+        Ok(targets[(record - first) as usize].clone())
+    }
+
+    /// Draw a record uniformly at random.
+    pub fn sample(&mut self) -> Result<Target, DecodeError> {
+        let record = self.rng.gen_range(0..self.index.records());
+        self.get(record)
+    }
+
+    /// Draw a batch of records uniformly at random (with replacement).
+    pub fn batch(&mut self, batch_size: usize) -> Result<Vec<Target>, DecodeError> {
+        (0..batch_size).map(|_| self.sample()).collect()
+    }
+
+    fn block(&mut self, block: usize) -> Result<Rc<[Target]>, DecodeError> {
+        if let Some(i) = self.cache.iter().position(|(b, _)| *b == block) {
+            let entry = self.cache.remove(i).expect("The position should be valid");
+            let targets = entry.1.clone();
+            self.cache.push_back(entry);
+            return Ok(targets);
+        }
+
+        let keyframe = self.index.keyframe(block);
+        let (records, bytes) = self.index.block_len(block);
+        self.input.seek(SeekFrom::Start(keyframe.offset))?;
+        let input = BufReader::new((&mut self.input).take(bytes));
+        let targets: Rc<[Target]> =
+            Decoder::<N, _>::with_header(input, self.index.header().clone())
+                .take(records as usize)
+                .map(|record| record.map(|record| record.to_target()))
+                .collect::<Result<_, _>>()?;
+
+        if self.cache.len() >= self.cache_size {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((block, targets.clone()));
+        Ok(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use fast_tak::Game;
+
+    use super::*;
+    use crate::codec::{Encoder, Record};
+
+    #[test]
+    fn test_sample_blocks() {
+        let games = [
+            Game::<5, 4>::from_ptn_moves(&["a1", "e5", "b1"]),
+            Game::<5, 4>::from_ptn_moves(&["a1", "e5", "b1", "b2"]),
+            Game::<5, 4>::from_ptn_moves(&["c3", "d3"]),
+            Game::<5, 4>::from_ptn_moves(&["c3", "d3", "d4"]),
+        ];
+        let mut output = vec![];
+        let mut encoder = Encoder::<5>::new();
+        for (i, game) in games.iter().enumerate() {
+            let record = Record::new(game.clone(), i as f32 / 4.0, vec![]);
+            encoder.write_record(&mut output, &record).unwrap();
+        }
+
+        let index = Index::build::<5>(output.as_slice()).unwrap();
+        assert_eq!(index.records(), 4);
+        assert_eq!(index.blocks(), 2);
+        assert_eq!(index.block_of(3), 1);
+
+        let mut sampler = Sampler::<5, _>::new(Cursor::new(output), index, 0, 1);
+        for i in [3, 0, 2, 1] {
+            let target = sampler.get(i).unwrap();
+            assert!((target.value.unwrap() - i as f32 / 4.0).abs() < 1e-4);
+        }
+        assert_eq!(sampler.batch(8).unwrap().len(), 8);
+    }
+}
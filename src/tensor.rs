@@ -0,0 +1,362 @@
+//! Network inputs and dense policies, ready to be loaded for training.
+//!
+//! States are encoded as `PLANES` planes of `N * N` squares, stored as `u8`.
+//! Square `(row, col)` of a plane is at `row * N + col`, with row 0 being rank 1
+//! and column 0 being file a.
+//!
+//! Planes:
+//! - `2 * layer` and `2 * layer + 1`: white / black piece at `layer` from the top
+//!   of the stack, for the top [`STACK_LAYERS`] layers.
+//! - [`WALL_PLANE`], [`CAP_PLANE`]: the top piece is a wall / capstone.
+//! - [`TO_MOVE_PLANE`]: 1 everywhere if white is to move.
+//! - [`RESERVES_PLANE`] to `RESERVES_PLANE + 3`: white stones, white caps,
+//!   black stones, and black caps left in reserve, repeated on every square.
+//! - [`KOMI_PLANE`]: half komi, repeated on every square.
+//!
+//! Policies are dense `f32` vectors indexed by [`move_index`].
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use fast_tak::Game;
+use takparse::{Color, Direction, Move, MoveKind, Piece, Square};
+
+use crate::{
+    json::quote,
+    npy::{pack_npz, NpyWriter},
+};
+
+pub const STACK_LAYERS: usize = 10;
+pub const WALL_PLANE: usize = 2 * STACK_LAYERS;
+pub const CAP_PLANE: usize = WALL_PLANE + 1;
+pub const TO_MOVE_PLANE: usize = CAP_PLANE + 1;
+pub const RESERVES_PLANE: usize = TO_MOVE_PLANE + 1;
+pub const KOMI_PLANE: usize = RESERVES_PLANE + 4;
+pub const PLANES: usize = KOMI_PLANE + 1;
+
+/// Append the input planes of the state.
+pub fn state_planes<const N: usize, const HALF_KOMI: i8>(
+    game: &Game<N, HALF_KOMI>,
+    planes: &mut Vec<u8>,
+) {
+    let start = planes.len();
+    planes.resize(start + PLANES * N * N, 0);
+    let planes = &mut planes[start..];
+    let mut set = |plane: usize, square: usize, value: u8| planes[plane * N * N + square] = value;
+
+    for row in 0..N {
+        for col in 0..N {
+            let square = row * N + col;
+            let stack = game
+                .board
+                .get(Square::new(col as u8, row as u8))
+                .expect("The square should be on the board");
+            if let Some((piece, _)) = stack.top() {
+                match piece {
+                    Piece::Flat => {}
+                    Piece::Wall => set(WALL_PLANE, square, 1),
+                    Piece::Cap => set(CAP_PLANE, square, 1),
+                }
+                // Colors are listed from the bottom, so reverse to start at the top.
+                let colors: Vec<_> = stack.colors().into_iter().collect();
+                for (layer, color) in colors.into_iter().rev().take(STACK_LAYERS).enumerate() {
+                    let plane = 2 * layer + usize::from(color == Color::Black);
+                    set(plane, square, 1);
+                }
+            }
+
+            set(
+                TO_MOVE_PLANE,
+                square,
+                u8::from(game.to_move == Color::White),
+            );
+            set(RESERVES_PLANE, square, game.white_reserves.stones);
+            set(RESERVES_PLANE + 1, square, game.white_reserves.caps);
+            set(RESERVES_PLANE + 2, square, game.black_reserves.stones);
+            set(RESERVES_PLANE + 3, square, game.black_reserves.caps);
+            set(KOMI_PLANE, square, HALF_KOMI as u8);
+        }
+    }
+}
+
+/// Number of spread patterns with the carry limit of an `N` board.
+const fn patterns<const N: usize>() -> usize {
+    (1 << N) - 1
+}
+
+/// Length of the dense policy vector.
+pub const fn policy_size<const N: usize>() -> usize {
+    3 * N * N + 4 * N * N * patterns::<N>()
+}
+
+/// Position of the move in the dense policy vector.
+///
+/// Placements come first, at `square * 3 + piece` (flat, wall, cap),
+/// followed by spreads at `3 * N * N + (square * 4 + direction) * (2^N - 1) + pattern`,
+/// where square is `row * N + col`, direction is up, down, left, or right,
+/// and pattern is the pattern mask shifted right by `8 - N`, minus one.
+pub fn move_index<const N: usize>(action: Move) -> usize {
+    let square = usize::from(action.square().row()) * N + usize::from(action.square().column());
+    match action.kind() {
+        MoveKind::Place(piece) => {
+            let piece = match piece {
+                Piece::Flat => 0,
+                Piece::Wall => 1,
+                Piece::Cap => 2,
+            };
+            square * 3 + piece
+        }
+        MoveKind::Spread(direction, pattern) => {
+            let direction = match direction {
+                Direction::Up => 0,
+                Direction::Down => 1,
+                Direction::Left => 2,
+                Direction::Right => 3,
+            };
+            let pattern = usize::from(pattern.mask() >> (8 - N)) - 1;
+            3 * N * N + (square * 4 + direction) * patterns::<N>() + pattern
+        }
+    }
+}
+
+/// Append the dense policy vector.
+pub fn dense_policy<const N: usize>(policy: &[(Move, f32)], dense: &mut Vec<f32>) {
+    let start = dense.len();
+    dense.resize(start + policy_size::<N>(), 0.0);
+    for &(action, probability) in policy {
+        dense[start + move_index::<N>(action)] = probability;
+    }
+}
+
+/// Writes states, values, and dense policies as raw little-endian arrays
+/// into a directory, together with a `schema.json` which describes them.
+pub struct TensorWriter<const N: usize> {
+    directory: PathBuf,
+    states: BufWriter<File>,
+    values: BufWriter<File>,
+    policies: BufWriter<File>,
+    records: usize,
+    planes: Vec<u8>,
+    policy: Vec<f32>,
+}
+
+impl<const N: usize> TensorWriter<N> {
+    pub fn create(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)?;
+        let create = |name: &str| {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(directory.join(name))
+                .map(BufWriter::new)
+        };
+        Ok(Self {
+            states: create("states.u8")?,
+            values: create("values.f32")?,
+            policies: create("policies.f32")?,
+            directory,
+            records: 0,
+            planes: vec![],
+            policy: vec![],
+        })
+    }
+
+    pub fn write<const HALF_KOMI: i8>(
+        &mut self,
+        state: &Game<N, HALF_KOMI>,
+        value: f32,
+        policy: &[(Move, f32)],
+    ) -> io::Result<()> {
+        self.planes.clear();
+        state_planes(state, &mut self.planes);
+        self.states.write_all(&self.planes)?;
+
+        self.values.write_all(&value.to_le_bytes())?;
+
+        self.policy.clear();
+        dense_policy::<N>(policy, &mut self.policy);
+        for p in &self.policy {
+            self.policies.write_all(&p.to_le_bytes())?;
+        }
+
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Flush the arrays and write the schema.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.states.flush()?;
+        self.values.flush()?;
+        self.policies.flush()?;
+        let records = self.records;
+        let spread = format!(
+            "{} + (square * 4 + direction (up, down, left, right)) * {} + (pattern_mask >> {}) - 1",
+            3 * N * N,
+            patterns::<N>(),
+            8 - N
+        );
+        let schema = format!(
+            r#"{{
+  "records": {records},
+  "board_size": {N},
+  "byte_order": "little",
+  "arrays": {{
+    "states": {{ "file": "states.u8", "dtype": "uint8", "shape": [{records}, {PLANES}, {N}, {N}] }},
+    "values": {{ "file": "values.f32", "dtype": "float32", "shape": [{records}] }},
+    "policies": {{ "file": "policies.f32", "dtype": "float32", "shape": [{records}, {policy_size}] }}
+  }},
+  "planes": {{
+    "stack_layers": {{ "layers": {STACK_LAYERS}, "plane": {layer} }},
+    "wall": {WALL_PLANE},
+    "cap": {CAP_PLANE},
+    "white_to_move": {TO_MOVE_PLANE},
+    "reserves": ["white_stones", "white_caps", "black_stones", "black_caps"],
+    "reserves_start": {RESERVES_PLANE},
+    "half_komi": {KOMI_PLANE}
+  }},
+  "policy_index": {{
+    "place": {place},
+    "spread": {spread},
+    "square": {square}
+  }}
+}}
+"#,
+            policy_size = policy_size::<N>(),
+            layer = quote("2 * layer_from_top + (0 white, 1 black)"),
+            place = quote("square * 3 + piece (flat, wall, cap)"),
+            spread = quote(&spread),
+            square = quote(&format!("row * {N} + column")),
+        );
+        std::fs::write(self.directory.join("schema.json"), schema)
+    }
+}
+
+/// Writes states, values, UBE, and CSR-style policies as NumPy arrays,
+/// either into a directory of `.npy` files or into an `.npz` archive.
+///
+/// The policy of record `i` consists of `policy_indices[policy_offsets[i]..policy_offsets[i + 1]]`
+/// (see [`move_index`]) and the matching `policy_probs`.
+pub struct NpzWriter<const N: usize> {
+    archive: Option<PathBuf>,
+    directory: PathBuf,
+    states: NpyWriter<u8>,
+    values: NpyWriter<f32>,
+    ube: NpyWriter<f32>,
+    policy_offsets: NpyWriter<i64>,
+    policy_indices: NpyWriter<u32>,
+    policy_probs: NpyWriter<f32>,
+    entries: i64,
+    planes: Vec<u8>,
+}
+
+impl<const N: usize> NpzWriter<N> {
+    const ARRAYS: [&'static str; 6] = [
+        "states.npy",
+        "values.npy",
+        "ube.npy",
+        "policy_offsets.npy",
+        "policy_indices.npy",
+        "policy_probs.npy",
+    ];
+
+    /// If the path ends in `.npz` the arrays are packed into an archive,
+    /// otherwise they are written into a directory.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (archive, directory) = if path.extension().is_some_and(|e| e == "npz") {
+            let mut directory = path.as_os_str().to_owned();
+            directory.push(".arrays");
+            (Some(path.to_path_buf()), PathBuf::from(directory))
+        } else {
+            (None, path.to_path_buf())
+        };
+        std::fs::create_dir_all(&directory)?;
+        let [states, values, ube, policy_offsets, policy_indices, policy_probs] =
+            Self::ARRAYS.map(|name| directory.join(name));
+
+        let mut policy_offsets = NpyWriter::create(policy_offsets)?;
+        policy_offsets.write(&[0])?;
+        Ok(Self {
+            archive,
+            states: NpyWriter::create(states)?,
+            values: NpyWriter::create(values)?,
+            ube: NpyWriter::create(ube)?,
+            policy_offsets,
+            policy_indices: NpyWriter::create(policy_indices)?,
+            policy_probs: NpyWriter::create(policy_probs)?,
+            directory,
+            entries: 0,
+            planes: vec![],
+        })
+    }
+
+    /// A missing UBE is stored as NaN.
+    pub fn write<const HALF_KOMI: i8>(
+        &mut self,
+        state: &Game<N, HALF_KOMI>,
+        value: f32,
+        ube: Option<f32>,
+        policy: &[(Move, f32)],
+    ) -> io::Result<()> {
+        self.planes.clear();
+        state_planes(state, &mut self.planes);
+        self.states.write(&self.planes)?;
+        self.values.write(&[value])?;
+        self.ube.write(&[ube.unwrap_or(f32::NAN)])?;
+
+        for &(action, probability) in policy {
+            self.policy_indices
+                .write(&[move_index::<N>(action) as u32])?;
+            self.policy_probs.write(&[probability])?;
+        }
+        self.entries += policy.len() as i64;
+        self.policy_offsets.write(&[self.entries])?;
+        Ok(())
+    }
+
+    /// Write the array headers, and pack them if writing an archive.
+    pub fn finish(self) -> io::Result<()> {
+        self.states.finish(&[PLANES, N, N])?;
+        self.values.finish(&[])?;
+        self.ube.finish(&[])?;
+        self.policy_offsets.finish(&[])?;
+        self.policy_indices.finish(&[])?;
+        self.policy_probs.finish(&[])?;
+        if let Some(archive) = self.archive {
+            let arrays = Self::ARRAYS.map(|name| self.directory.join(name));
+            pack_npz(archive, &arrays)?;
+            std::fs::remove_dir(self.directory)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_move_index_is_unique() {
+        let mut moves = vec![];
+        let game = Game::<5, 4>::from_ptn_moves(&["a1", "e5", "c3", "d3", "c3>", "c3", "2d3<"]);
+        game.possible_moves(&mut moves);
+        let indices: HashSet<_> = moves.iter().map(|&m| move_index::<5>(m)).collect();
+        assert_eq!(indices.len(), moves.len());
+        assert!(indices.iter().all(|&i| i < policy_size::<5>()));
+
+        let mut planes = vec![];
+        state_planes(&game, &mut planes);
+        assert_eq!(planes.len(), PLANES * 5 * 5);
+        // c3 has a white piece on top of two black ones.
+        let c3 = 2 * 5 + 2;
+        let layers: Vec<_> = (0..6).map(|plane| planes[plane * 25 + c3]).collect();
+        assert_eq!(layers, [1, 0, 0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Policies as the visit counts of a search.
+//!
+//! If the [`Header`](crate::header::Header) declares visit counts, the policy of every record
+//! is stored as the total number of visits as a varint, followed by the moves which were visited,
+//! terminated by a zero byte, and then their counts. Unvisited moves are left out.
+//! Unlike the quantized log-probabilities this is lossless.
+//!
+//! The counts are entropy-coded with Exp-Golomb codes, whose order the encoder picks
+//! for every record as the one with the fewest bits, and stores in the first 5 bits.
+//! A count of `c` costs about `2 * log2(c / 2^order + 1) + order + 1` bits,
+//! so the many moves with a few visits take a few bits each, and the best move does not take many more.
+//! The bits are packed like a state and padded to a whole byte.
+//! Records are decoded on their own, so there is no adaptive model across records,
+//! which would need a range coder to be flushed at every record.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use bitvec::{order::Lsb0, vec::BitVec};
+use takparse::Move;
+
+use crate::{
+    codec::{read_action, write_action, BitIterator, DecodeError},
+    metadata::{read_varint, write_varint},
+    ParseTargetError,
+};
+
+/// Number of bits of the order of the Exp-Golomb codes.
+const ORDER_BITS: usize = 5;
+/// A count has at most 32 bits, so its code has at most this many leading zeros.
+const MAX_ZEROS: u32 = 32;
+
+/// The visit counts of a search, from which the policy is normalized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Visits {
+    /// The visits of the root, which may be more than the sum of the counts.
+    pub total: u32,
+    /// Moves which were not visited may be left out.
+    pub counts: Box<[(Move, u32)]>,
+}
+
+impl Visits {
+    /// The counts divided by their sum, or zeros if nothing was visited.
+    pub fn policy(&self) -> Box<[(Move, f32)]> {
+        let sum: u64 = self.counts.iter().map(|&(_, count)| u64::from(count)).sum();
+        self.counts
+            .iter()
+            .map(|&(action, count)| {
+                let probability = if sum == 0 {
+                    0.0
+                } else {
+                    (count as f64 / sum as f64) as f32
+                };
+                (action, probability)
+            })
+            .collect()
+    }
+
+    /// Write the total, the visited moves, and their coded counts.
+    pub fn write(&self, output: &mut impl Write) -> io::Result<usize> {
+        let mut bytes = vec![];
+        write_varint(&mut bytes, self.total.into());
+        let visited: Vec<_> = self
+            .counts
+            .iter()
+            .filter(|&&(_, count)| count > 0)
+            .collect();
+        for &&(action, _) in &visited {
+            write_action(&mut bytes, Some(action))?;
+        }
+        write_action(&mut bytes, None)?;
+
+        if !visited.is_empty() {
+            // Zero is never stored, so the codes start at a count of one.
+            let values: Vec<_> = visited.iter().map(|&&(_, count)| count - 1).collect();
+            let order = (0..1 << ORDER_BITS)
+                .min_by_key(|&order| values.iter().map(|&x| code_len(x, order)).sum::<u32>())
+                .expect("There are orders to pick from");
+            let mut bitvec = BitVec::<u8, Lsb0>::new();
+            let order_bitvec = BitVec::<u8, Lsb0>::from_element(order as u8);
+            bitvec.extend(order_bitvec.into_iter().take(ORDER_BITS));
+            for x in values {
+                push_code(&mut bitvec, x, order);
+            }
+            bytes.extend(bitvec.into_vec());
+        }
+        output.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    pub fn read(input: &mut impl Read) -> Result<Self, DecodeError> {
+        let total = u32::try_from(read_varint(input)?).map_err(|_| out_of_range())?;
+        let mut actions = vec![];
+        while let Some(action) = read_action(input)? {
+            actions.push(action);
+        }
+        let mut counts = vec![];
+        if !actions.is_empty() {
+            let mut bits = BitIterator::new();
+            let mut order = 0;
+            for i in 0..ORDER_BITS {
+                order |= u32::from(bits.next(input)?) << i;
+            }
+            for action in actions {
+                let x = read_code(|| bits.next(input), order)?;
+                let count = x.checked_add(1).ok_or_else(out_of_range)?;
+                counts.push((action, count));
+            }
+        }
+        Ok(Self {
+            total,
+            counts: counts.into_boxed_slice(),
+        })
+    }
+}
+
+fn out_of_range() -> DecodeError {
+    io::Error::new(io::ErrorKind::InvalidData, "visit count out of range").into()
+}
+
+/// The number of bits of the Exp-Golomb code of `x`.
+fn code_len(x: u32, order: u32) -> u32 {
+    let len = u64::BITS - (u64::from(x) + (1 << order)).leading_zeros();
+    2 * len - order - 1
+}
+
+/// Push the Exp-Golomb code of `x`: the bits of `x + 2^order` with its highest bit first,
+/// after as many zeros as it has bits more than `order + 1`.
+fn push_code(bitvec: &mut BitVec<u8, Lsb0>, x: u32, order: u32) {
+    let shifted = u64::from(x) + (1 << order);
+    let len = u64::BITS - shifted.leading_zeros();
+    bitvec.extend((order + 1..len).map(|_| false));
+    bitvec.extend((0..len).rev().map(|i| (shifted >> i) & 1 == 1));
+}
+
+fn read_code(
+    mut next_bit: impl FnMut() -> io::Result<bool>,
+    order: u32,
+) -> Result<u32, DecodeError> {
+    let mut zeros = 0;
+    while !next_bit()? {
+        zeros += 1;
+        if zeros > MAX_ZEROS {
+            return Err(out_of_range());
+        }
+    }
+    let mut shifted = 1u64;
+    for _ in 0..zeros + order {
+        shifted = (shifted << 1) | u64::from(next_bit()?);
+    }
+    u32::try_from(shifted - (1 << order)).map_err(|_| out_of_range())
+}
+
+/// Parses `{total},{move}:{count},...`, such as `800,a1:500,Sb2:299`.
+impl FromStr for Visits {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.split_terminator(',');
+        let total = iter.next().ok_or(ParseTargetError::WrongPolicyFormat)?;
+        let counts = iter
+            .map(|s| {
+                s.split_once(':')
+                    .ok_or(ParseTargetError::WrongPolicyFormat)
+                    .and_then(|(a, count)| Ok((a.parse()?, count.parse()?)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            total: total.parse()?,
+            counts,
+        })
+    }
+}
+
+impl fmt::Display for Visits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.total)?;
+        for (action, count) in self.counts.iter() {
+            write!(f, ",{action}:{count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visits_round_trip() {
+        let visits: Visits = "81,a1:50,Sb2:30,c3:0".parse().unwrap();
+        assert_eq!(visits.to_string(), "81,a1:50,Sb2:30,c3:0");
+        assert_eq!(visits.policy()[0].1, 0.625);
+
+        let mut bytes = vec![];
+        let written = visits.write(&mut bytes).unwrap();
+        // One byte for the total, two per visited move, the terminator,
+        // and the order and the codes of 49 and 29 with order 6 in 5 + 6 + 6 bits.
+        assert_eq!(written, 1 + 2 * 2 + 1 + 3);
+        assert_eq!(bytes.len(), written);
+        let decoded = Visits::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(decoded.total, visits.total);
+        assert_eq!(decoded.counts[..], visits.counts[..2]);
+    }
+
+    #[test]
+    fn test_visit_codes() {
+        for order in [0, 3, 31] {
+            for x in [0, 1, 2, 7, 100, 65_535, u32::MAX - 1, u32::MAX] {
+                let mut bitvec = BitVec::<u8, Lsb0>::new();
+                push_code(&mut bitvec, x, order);
+                assert_eq!(bitvec.len() as u32, code_len(x, order));
+                let mut bits = bitvec.into_iter();
+                assert_eq!(read_code(|| Ok(bits.next().unwrap()), order).unwrap(), x);
+            }
+        }
+
+        // A single move with all of the visits, and an unvisited search.
+        for visits in ["4294967295,a1:4294967295", "0"] {
+            let visits: Visits = visits.parse().unwrap();
+            let mut bytes = vec![];
+            visits.write(&mut bytes).unwrap();
+            bytes.push(0xAB);
+            let mut input = bytes.as_slice();
+            assert_eq!(Visits::read(&mut input).unwrap(), visits);
+            assert_eq!(input, [0xAB]);
+        }
+
+        // A corrupt code which is longer than any count.
+        let mut bytes = vec![1];
+        write_action(&mut bytes, Some("a1".parse().unwrap())).unwrap();
+        bytes.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(Visits::read(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+//! Round trips through the tools of the `targets` binary.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fast_tak::Game;
+use takparse::{Move, Tps};
+
+/// A temporary directory which is removed at the end of the test.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("compress-targets-{name}-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.0.join(file).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn targets(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_targets"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn run(args: &[&str]) {
+    let output = targets(args);
+    assert!(
+        output.status.success(),
+        "targets {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Text targets for every position of the games, with a uniform policy.
+fn text_targets(games: &[&[&str]]) -> String {
+    let mut lines = String::new();
+    for (i, moves) in games.iter().enumerate() {
+        for ply in 0..=moves.len() {
+            let game = Game::<5, 4>::from_ptn_moves(&moves[..ply]);
+            let mut actions: Vec<Move> = vec![];
+            game.possible_moves(&mut actions);
+            let probability = 1.0 / actions.len() as f32;
+            let policy: Vec<_> = actions
+                .iter()
+                .map(|action| format!("{action}:{probability}"))
+                .collect();
+            let value = (i as f32 - ply as f32 / 10.0).clamp(-1.0, 1.0);
+            lines += &format!("{};{value};{}\n", Tps::from(game), policy.join(","));
+        }
+    }
+    lines
+}
+
+/// Two games, where the second one is the mirror image of the start of the first.
+const GAMES: &[&[&str]] = &[
+    &["a1", "e5", "c3", "d4", "b2", "b3", "Cc2", "e4"],
+    &["e1", "a5", "c3", "b4"],
+];
+
+/// Compress the targets into `name.bin` and return its path.
+fn compress(dir: &TempDir, name: &str, text: &str) -> String {
+    let input = dir.path(&format!("{name}.txt"));
+    let output = dir.path(&format!("{name}.bin"));
+    fs::write(&input, text).unwrap();
+    run(&["compress", &input, "--output", &output, "--size", "5"]);
+    output
+}
+
+/// The decompressed lines without the move number, which is not stored with full states.
+fn decompressed(path: &str) -> Vec<String> {
+    let output = targets(&["decompress", path, "--size", "5"]);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (tps, rest) = line.split_once(';').unwrap();
+            let tps: Vec<_> = tps.split(' ').take(2).collect();
+            format!("{};{rest}", tps.join(" "))
+        })
+        .collect()
+}
+
+fn sorted(mut lines: Vec<String>) -> Vec<String> {
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_rejects() {
+    let dir = TempDir::new("rejects");
+    let input = dir.path("input.txt");
+    let lines = [
+        "x5/x5/x5/x5/x5 1 1;0.5;a1:1",
+        "x5/x5/x5/x5/x5 1 1;1.5;a1:1",
+        "x5/x5/x5/x5/x5 1 1;half;a1:1",
+        "x5/x5/x5/x5/x5 1 1;0.5;a6:1",
+        "x5/x5/x5/x5/x5 1 1;-1.5;b1:1",
+    ];
+    fs::write(&input, lines.join("\n") + "\n").unwrap();
+    let output = dir.path("output.bin");
+    let rejects = dir.path("rejects.txt");
+    let compress = |extra: &[&str]| {
+        let mut args = vec!["compress", &input, "--output", &output, "--size", "5"];
+        args.extend(extra);
+        targets(&args)
+    };
+
+    let skipped = compress(&["--reject-file", &rejects]);
+    assert!(skipped.status.success());
+    let stdout = String::from_utf8(skipped.stdout).unwrap();
+    assert!(stdout.contains("Skipped 4 inputs:"), "{stdout}");
+    let counts: Vec<_> = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("Skipped"))
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    assert_eq!(counts, ["Float 1", "IllegalMove 1", "ValueOutOfRange 2"]);
+    let rejected = fs::read_to_string(&rejects).unwrap();
+    let rejected: Vec<_> = rejected.lines().collect();
+    assert_eq!(rejected.len(), 8);
+    assert_eq!(rejected[0], "# line 2: value 1.5 is not in [-1, 1]");
+    assert!(rejected[2].starts_with("# line 3: "), "{}", rejected[2]);
+    assert_eq!(rejected[4], "# line 4: move a6 is not legal");
+    for (i, line) in [1, 2, 3, 4].into_iter().enumerate() {
+        assert_eq!(rejected[2 * i + 1], lines[line]);
+    }
+    assert_eq!(decompressed(&output).len(), 1);
+
+    let strict = compress(&["--strict"]);
+    assert_eq!(strict.status.code(), Some(1));
+    let stderr = String::from_utf8(strict.stderr).unwrap();
+    assert!(
+        stderr.contains("Rejected line 2: value 1.5 is not in [-1, 1]"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_shuffle() {
+    let dir = TempDir::new("shuffle");
+    let original = compress(&dir, "original", &text_targets(GAMES));
+    let shuffled = dir.path("shuffled.bin");
+    run(&[
+        "shuffle", &original, "--output", &shuffled, "--size", "5", "--seed", "3",
+    ]);
+    let expected = decompressed(&original);
+    let lines = decompressed(&shuffled);
+    assert_ne!(lines, expected);
+    assert_eq!(sorted(lines), sorted(expected));
+    assert!(!fs::read_dir(&dir.0).unwrap().any(|entry| entry
+        .unwrap()
+        .path()
+        .extension()
+        .is_some_and(|e| e == "tmp")));
+}
+
+#[test]
+fn test_split_concat_slice() {
+    let dir = TempDir::new("split");
+    let original = compress(&dir, "original", &text_targets(GAMES));
+    let expected = decompressed(&original);
+
+    run(&[
+        "split",
+        &original,
+        "--output",
+        &dir.path("part"),
+        "--size",
+        "5",
+        "--records",
+        "4",
+    ]);
+    let parts: Vec<_> = (0..expected.len().div_ceil(4))
+        .map(|i| dir.path(&format!("part.{i}.bin")))
+        .collect();
+    assert!(parts.iter().all(|part| Path::new(part).exists()));
+    let concatenated = dir.path("concatenated.bin");
+    let mut args = vec!["concat"];
+    args.extend(parts.iter().map(String::as_str));
+    args.extend(["--output", &concatenated, "--size", "5"]);
+    run(&args);
+    assert_eq!(decompressed(&concatenated), expected);
+
+    let sliced = dir.path("sliced.bin");
+    run(&[
+        "slice", &original, "--output", &sliced, "--size", "5", "--from", "3", "--to", "10",
+    ]);
+    assert_eq!(decompressed(&sliced), expected[3..10]);
+}
+
+#[test]
+fn test_dedupe() {
+    let dir = TempDir::new("dedupe");
+    let games = [GAMES[0], GAMES[1], GAMES[0]];
+    let original = compress(&dir, "original", &text_targets(&games));
+    let expected = decompressed(&original);
+    let deduped = dir.path("deduped.bin");
+    let dedupe = |args: &[&str]| {
+        let mut all = vec!["dedupe", &original, "--output", &deduped, "--size", "5"];
+        all.extend(args);
+        run(&all);
+        decompressed(&deduped)
+    };
+
+    // The second game only repeats the empty board.
+    let first = dedupe(&["--merge", "first"]);
+    assert_eq!(first, [&expected[..9], &expected[10..14]].concat());
+    // It is the mirror image of the first one, whose orientation is kept.
+    let symmetric = dedupe(&["--merge", "first", "--symmetry"]);
+    assert_eq!(symmetric, expected[..9]);
+
+    let mean = dedupe(&["--merge", "mean", "--symmetry"]);
+    assert_eq!(mean.len(), 9);
+    let tps = |line: &String| line.split_once(';').unwrap().0.to_string();
+    for (merged, first) in mean.iter().zip(&symmetric) {
+        assert_eq!(tps(merged), tps(first));
+    }
+    assert_ne!(mean[0], symmetric[0]);
+}
+
+#[test]
+fn test_dedupe_visits() {
+    let dir = TempDir::new("dedupe-visits");
+    let input = dir.path("visits.txt");
+    let text =
+        "x5/x5/x5/x5/x5 1 1;0.5;100,a1:60,b1:40\nx5/x5/x5/x5/x5 1 1;-0.5;300,a1:100,e5:200\n";
+    fs::write(&input, text).unwrap();
+    let original = dir.path("visits.bin");
+    run(&[
+        "compress",
+        &input,
+        "--output",
+        &original,
+        "--size",
+        "5",
+        "--channels",
+        "value,visits",
+    ]);
+    let deduped = dir.path("deduped.bin");
+    let merged = |merge: &str| {
+        run(&[
+            "dedupe", &original, "--output", &deduped, "--size", "5", "--merge", merge,
+        ]);
+        let lines = decompressed(&deduped);
+        assert_eq!(lines.len(), 1);
+        let fields: Vec<_> = lines[0].split(';').map(String::from).collect();
+        (fields[1].parse::<f32>().unwrap(), fields[2].clone())
+    };
+
+    // Both merges add up the visits, but only one weights the values by them.
+    let (mean, visits) = merged("mean");
+    assert!(mean.abs() < 1e-4, "{mean}");
+    assert_eq!(visits, "400,a1:160,b1:40,e5:200");
+    let (weighted, visits) = merged("visits");
+    assert!((weighted + 0.25).abs() < 1e-4, "{weighted}");
+    assert_eq!(visits, "400,a1:160,b1:40,e5:200");
+
+    let policy = compress(&dir, "policy", &text_targets(GAMES));
+    let output = targets(&[
+        "dedupe", &policy, "--output", &deduped, "--size", "5", "--merge", "visits",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_diffs_to_ptn_and_check() {
+    let dir = TempDir::new("diffs");
+    // The whole first game, and then pairs of positions which skip the plies between them.
+    let game: Vec<_> = text_targets(&GAMES[..1])
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut lines = game.clone();
+    lines.extend([0, 1, 3, 4, 6, 7].map(|ply| game[ply].clone()));
+    let text = lines.join("\n") + "\n";
+    let original = compress(&dir, "original", &text);
+
+    let inspect = targets(&["inspect", &original, "--size", "5"]);
+    assert!(inspect.status.success());
+    let report = String::from_utf8(inspect.stdout).unwrap();
+    assert!(
+        report.contains("Full states: 2 (13.3%), relative states: 13 (86.7%), of which diffs: 2"),
+        "{report}"
+    );
+
+    // Every diff starts a new game from its position.
+    let ptn = dir.path("games.ptn");
+    run(&["to-ptn", &original, "--output", &ptn, "--size", "5"]);
+    let games = fs::read_to_string(&ptn).unwrap();
+    assert_eq!(games.matches("[Size \"5\"]").count(), 4);
+    assert_eq!(games.matches("[TPS ").count(), 2);
+
+    let input = dir.path("original.txt");
+    run(&["check", &input, &original, "--size", "5", "--summary"]);
+    let shorter = dir.path("shorter.txt");
+    fs::write(&shorter, lines[1..].join("\n") + "\n").unwrap();
+    let output = targets(&["check", &shorter, &original, "--size", "5", "--summary"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_inspect() {
+    let dir = TempDir::new("inspect");
+    let original = compress(&dir, "original", &text_targets(GAMES));
+    let output = targets(&["inspect", &original, "--size", "5"]);
+    assert!(output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines[0], "Channels: value, policy");
+    assert_eq!(lines[1], "Records: 14");
+    assert_eq!(
+        lines[2],
+        "Full states: 2 (14.3%), relative states: 12 (85.7%), of which diffs: 0 (0.0%)"
+    );
+    // The parts add up to the whole file, which has no header with the default channels.
+    let size = fs::metadata(&original).unwrap().len();
+    assert!(
+        lines[3].starts_with(&format!("Bytes: {size} (")),
+        "{report}"
+    );
+    let part = |name: &str| -> u64 {
+        let line = lines
+            .iter()
+            .find(|line| line.trim_start().starts_with(name));
+        line.unwrap()
+            .split_whitespace()
+            .nth(1)
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    // Two bytes for each move and one to mark each full state, and two for each value.
+    assert_eq!(part("actions"), 12 * 2 + 2);
+    assert_eq!(part("values"), 14 * 2);
+    assert_eq!(part("metadata"), 0);
+    let parts: u64 = ["actions", "states", "values", "metadata", "policies"]
+        .map(part)
+        .iter()
+        .sum();
+    assert_eq!(parts, size);
+    assert!(report.contains("Records per game:\n  [   0.00,   10.00)          2 "));
+}
+
+#[test]
+fn test_check_compressed() {
+    let dir = TempDir::new("check");
+    let compressed = compress(&dir, "original", &text_targets(GAMES));
+    let original = dir.path("original.txt");
+
+    // The compressed file is decoded in-process, without a decompressed text file.
+    let output = targets(&["check", &original, &compressed, "--size", "5", "--summary"]);
+    assert!(output.status.success());
+    let summary = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        summary.starts_with(r#"{"records":14,"compared":14,"mismatched":0,"#),
+        "{summary}"
+    );
+    assert!(summary.ends_with("\"mismatches\":[]}\n"), "{summary}");
+
+    // Without the size it is read as text.
+    let output = targets(&["check", &original, &compressed, "--summary"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // A truncated file is compared up to the record which cannot be decoded.
+    let truncated = dir.path("truncated.bin");
+    let bytes = fs::read(&compressed).unwrap();
+    fs::write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+    let output = targets(&["check", &original, &truncated, "--size", "5", "--summary"]);
+    assert_eq!(output.status.code(), Some(1));
+    let summary = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        summary.contains(r#""compared":13,"mismatched":1,"#),
+        "{summary}"
+    );
+    assert!(
+        summary.contains(r#"{"record":13,"reason":"could not decode converted: "#),
+        "{summary}"
+    );
+}